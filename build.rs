@@ -0,0 +1,28 @@
+use std::{fs, path::Path, process::Command};
+
+/// Embeds the git revision the profiler writes into its dumps.
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    // A commit moves the branch HEAD points to, not HEAD itself.
+    if let Some(reference) = fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| Some(head.strip_prefix("ref: ")?.trim().to_owned()))
+    {
+        println!("cargo:rerun-if-changed=.git/{}", reference);
+    }
+    if Path::new(".git/packed-refs").exists() {
+        println!("cargo:rerun-if-changed=.git/packed-refs");
+    }
+    // Edits to the sources change the `-dirty` suffix.
+    println!("cargo:rerun-if-changed=src");
+    let revision = Command::new("git")
+        .args(["describe", "--always", "--dirty"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(revision) = revision {
+        println!("cargo:rustc-env=GIT_REVISION={}", revision.trim());
+    }
+}
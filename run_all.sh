@@ -1,5 +0,0 @@
-#!/bin/bash
-
-./run_const_nodes.sh
-./run_long_sim.sh
-./run_square.sh
\ No newline at end of file
@@ -1,8 +0,0 @@
-#!/bin/bash
-
-xs=(64   128  256  512  1024 2048 4086 8192)
-ys=(8192 4096 2048 1024 512  256  128  64)
-
-for i in {0..7}; do
-    ./run.sh ${xs[${i}]} ${ys[${i}]}
-done
\ No newline at end of file
@@ -0,0 +1,243 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    error::Error,
+    fmt::{self, Display, Formatter},
+    ptr,
+    str::FromStr,
+    sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+};
+
+use jemallocator::Jemalloc;
+
+/// Environment variable naming the backend of the `SelectableAllocator`.
+pub const ALLOCATOR_VAR: &str = "TRAJECTORY_ALLOCATOR";
+const ALLOCATOR_VAR_NUL: &[u8] = b"TRAJECTORY_ALLOCATOR\0";
+
+/// Address space reserved for the bump allocator. Pages are only backed once touched.
+pub const BUMP_CAPACITY: usize = 1 << 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Jemalloc,
+    /// The allocator of the C library.
+    System,
+    /// Hands out consecutive addresses and never reuses them, so freeing is a no-op.
+    Bump,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 3] = [Backend::Jemalloc, Backend::System, Backend::Bump];
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Jemalloc => "jemalloc",
+            Backend::System => "system",
+            Backend::Bump => "bump",
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Backend {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Backend::ALL
+            .iter()
+            .copied()
+            .find(|backend| backend.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown allocator `{}`, expected jemalloc, system or bump",
+                    s
+                )
+                .into()
+            })
+    }
+}
+
+/// 0 until the first allocation through the `SelectableAllocator`, the backend index plus one after.
+static SELECTED: AtomicU8 = AtomicU8::new(0);
+
+/// Backend in use if the `SelectableAllocator` is the global allocator.
+pub fn active() -> Option<Backend> {
+    match SELECTED.load(Ordering::Relaxed) {
+        0 => None,
+        selected => Some(Backend::ALL[selected as usize - 1]),
+    }
+}
+
+/// Global allocator that forwards to the backend named by `ALLOCATOR_VAR`, jemalloc if it is
+/// unset or unknown. The variable is read on the first allocation, before `main` runs, so the
+/// backend is fixed for the whole process.
+pub struct SelectableAllocator;
+
+impl SelectableAllocator {
+    fn backend(&self) -> Backend {
+        match active() {
+            Some(backend) => backend,
+            None => {
+                let backend = requested_backend();
+                let index = Backend::ALL.iter().position(|&b| b == backend).unwrap();
+                SELECTED.store(index as u8 + 1, Ordering::Relaxed);
+                backend
+            }
+        }
+    }
+}
+
+/// Reads the environment without allocating, as it runs inside the first allocation.
+fn requested_backend() -> Backend {
+    // SAFETY: the name is nul terminated and the value is only read before anything can set it.
+    let value = unsafe { libc::getenv(ALLOCATOR_VAR_NUL.as_ptr() as *const libc::c_char) };
+    if value.is_null() {
+        return Backend::Jemalloc;
+    }
+    // SAFETY: `getenv` returns a nul terminated string.
+    let value = unsafe { std::ffi::CStr::from_ptr(value) }.to_bytes();
+    Backend::ALL
+        .iter()
+        .copied()
+        .find(|backend| backend.name().as_bytes() == value)
+        .unwrap_or(Backend::Jemalloc)
+}
+
+unsafe impl GlobalAlloc for SelectableAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.backend() {
+            Backend::Jemalloc => Jemalloc.alloc(layout),
+            Backend::System => System.alloc(layout),
+            Backend::Bump => Bump.alloc(layout),
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match self.backend() {
+            Backend::Jemalloc => Jemalloc.alloc_zeroed(layout),
+            Backend::System => System.alloc_zeroed(layout),
+            Backend::Bump => Bump.alloc_zeroed(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.backend() {
+            Backend::Jemalloc => Jemalloc.dealloc(ptr, layout),
+            Backend::System => System.dealloc(ptr, layout),
+            Backend::Bump => Bump.dealloc(ptr, layout),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match self.backend() {
+            Backend::Jemalloc => Jemalloc.realloc(ptr, layout, new_size),
+            Backend::System => System.realloc(ptr, layout, new_size),
+            Backend::Bump => Bump.realloc(ptr, layout, new_size),
+        }
+    }
+}
+
+static BUMP_BASE: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+/// Offset of the first free byte from `BUMP_BASE`.
+static BUMP_NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Bump allocator over `BUMP_CAPACITY` bytes of address space mapped on first use.
+/// Memory is never reused, so every allocation starts out zeroed.
+struct Bump;
+
+impl Bump {
+    fn base(&self) -> *mut u8 {
+        let base = BUMP_BASE.load(Ordering::Acquire);
+        if !base.is_null() {
+            return base;
+        }
+        // SAFETY: an anonymous mapping does not alias anything.
+        let mapped = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                BUMP_CAPACITY,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if mapped == libc::MAP_FAILED {
+            return ptr::null_mut();
+        }
+        let mapped = mapped as *mut u8;
+        match BUMP_BASE.compare_exchange(
+            ptr::null_mut(),
+            mapped,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => mapped,
+            Err(base) => {
+                // Another thread mapped the region first.
+                unsafe { libc::munmap(mapped as *mut libc::c_void, BUMP_CAPACITY) };
+                base
+            }
+        }
+    }
+
+    /// Moves the end of the used region from `expected` to `end` unless it moved meanwhile.
+    fn try_extend(&self, expected: usize, end: Option<usize>) -> bool {
+        match end {
+            Some(end) if end <= BUMP_CAPACITY => BUMP_NEXT
+                .compare_exchange(expected, end, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok(),
+            _ => false,
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Bump {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let base = self.base();
+        if base.is_null() {
+            return base;
+        }
+        let mut next = BUMP_NEXT.load(Ordering::Relaxed);
+        loop {
+            let start = match (base as usize + next).checked_add(layout.align() - 1) {
+                Some(padded) => (padded & !(layout.align() - 1)) - base as usize,
+                None => return ptr::null_mut(),
+            };
+            let end = match start.checked_add(layout.size()) {
+                Some(end) if end <= BUMP_CAPACITY => end,
+                _ => return ptr::null_mut(),
+            };
+            match BUMP_NEXT.compare_exchange_weak(next, end, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return base.add(start),
+                Err(current) => next = current,
+            }
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+
+    /// Grows the most recent allocation in place, copies any other one.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size <= layout.size() {
+            return ptr;
+        }
+        let start = ptr as usize - self.base() as usize;
+        if self.try_extend(start + layout.size(), start.checked_add(new_size)) {
+            return ptr;
+        }
+        let new_ptr = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size());
+        }
+        new_ptr
+    }
+}
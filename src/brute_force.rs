@@ -0,0 +1,88 @@
+use std::error::Error;
+
+use crate::constraints::InfeasibleError;
+use crate::obstacles::ObstacleMask;
+use crate::score::Score;
+use crate::simulation::SimulationOptions;
+use crate::trajectory::Trajectory;
+
+/// Most row sequences the solver is willing to try.
+const MAX_SEQUENCES: f64 = 1e7;
+
+/// Tries every row sequence, which is only feasible for tiny grids.
+/// It shares nothing with the strategies but the cost field and the constraints,
+/// so it serves as the reference they are tested against.
+/// Trajectories of equal cost come in lexicographic order of their rows.
+pub fn brute_force(options: &SimulationOptions) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+    let (width, height) = (options.width, options.height);
+    if (height as f64).powi(width as i32) > MAX_SEQUENCES {
+        return Err(format!("a {}x{} grid is too large to brute force", width, height).into());
+    }
+    let mut found = Vec::new();
+    let mut rows = vec![0; width];
+    'sequences: loop {
+        if let Some(cost) = path_cost(options, &rows) {
+            found.push((cost, rows.clone()));
+        }
+        for x in (0..width).rev() {
+            rows[x] += 1;
+            if rows[x] < height {
+                continue 'sequences;
+            }
+            rows[x] = 0;
+        }
+        break;
+    }
+    found.sort_by_key(|(cost, _)| *cost);
+    found.truncate(options.paths);
+    if found.is_empty() {
+        return Err(InfeasibleError.into());
+    }
+    Ok(found
+        .into_iter()
+        .map(|(cost, rows)| Trajectory::new(cost, rows, &*options.cost_field))
+        .collect())
+}
+
+/// Total cost of the row sequence, `None` if it breaks a constraint.
+fn path_cost(options: &SimulationOptions, rows: &[usize]) -> Option<Score> {
+    let constraints = &options.constraints;
+    if !rows
+        .iter()
+        .enumerate()
+        .all(|(x, &y)| constraints.allows(x, y))
+    {
+        return None;
+    }
+    let mut cost = constraints.start.cost(rows[0]);
+    for x in 1..rows.len() {
+        let (y0, y1) = (rows[x - 1], rows[x]);
+        if options
+            .max_slope
+            .is_some_and(|max_slope| y0.abs_diff(y1) > max_slope)
+        {
+            return None;
+        }
+        if let Some(obstacles) = &constraints.obstacles {
+            if crosses_obstacle(obstacles, x, y0, y1) {
+                return None;
+            }
+        }
+        cost = (cost + options.cost_field.get_cost(x - 1, y0, x, y1))?;
+    }
+    Some(constraints.final_cost(rows[rows.len() - 1], cost)).filter(Score::is_finite)
+}
+
+/// Whether the edge from row `y0` of column `x - 1` to row `y1` of column `x` touches a blocked
+/// cell. The edge changes columns halfway between its rows, so each column contributes the cells
+/// from its own endpoint to the middle.
+fn crosses_obstacle(obstacles: &ObstacleMask, x: usize, y0: usize, y1: usize) -> bool {
+    let (low, high) = if y0 <= y1 {
+        ((x - 1, y0), (x, y1))
+    } else {
+        ((x, y1), (x - 1, y0))
+    };
+    let sum = low.1 + high.1;
+    (low.1..=sum / 2).any(|y| obstacles.is_blocked(low.0, y))
+        || (sum.div_ceil(2)..=high.1).any(|y| obstacles.is_blocked(high.0, y))
+}
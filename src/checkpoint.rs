@@ -0,0 +1,214 @@
+use std::error::Error;
+
+use crate::constraints::{check_feasible, Constraints};
+use crate::cost_field::CostField;
+use crate::memory_profiler::{AllocationData, Phase};
+use crate::score::{self, Score};
+use crate::simulation::{lowest_minimum, LeftNode, RightNode};
+use crate::simulation::{Simulation, SimulationOptions};
+use crate::trajectory::Trajectory;
+
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+struct Node {
+    y: usize,
+    parent: Option<usize>,
+    aggregated_cost: Score,
+}
+
+impl LeftNode for Node {
+    fn aggregated_cost(&self) -> Score {
+        self.aggregated_cost
+    }
+}
+impl RightNode for Node {
+    fn set_aggregated_cost(&mut self, score: Score) {
+        self.aggregated_cost = score;
+    }
+    fn aggregated_cost(&self) -> Score {
+        self.aggregated_cost
+    }
+}
+
+impl Node {
+    const fn new(y: usize, aggregated_cost: Score) -> Self {
+        Node {
+            y,
+            parent: None,
+            aggregated_cost,
+        }
+    }
+}
+
+/// During the forward pass only the two newest columns are kept,
+/// while a recomputed segment keeps every column so that it can be backtracked.
+struct SimulationSpace {
+    width: usize,
+    height: usize,
+    max_slope: Option<usize>,
+    constraints: Arc<Constraints>,
+    cost_field: Arc<dyn CostField>,
+    columns: Vec<Vec<Node>>,
+    origin: usize,
+    keep_columns: bool,
+}
+
+impl Simulation for SimulationSpace {
+    type LeftNodeType = Node;
+    type RightNodeType = Node;
+
+    fn get_cost_field(&self) -> Arc<dyn CostField> {
+        self.cost_field.clone()
+    }
+
+    fn max_slope(&self) -> Option<usize> {
+        self.max_slope
+    }
+
+    fn constraints(&self) -> Arc<Constraints> {
+        self.constraints.clone()
+    }
+
+    fn column(&self, iteration: usize) -> usize {
+        self.origin + iteration
+    }
+
+    fn prepare_step_slices(
+        &mut self,
+        _: usize,
+    ) -> (&[Self::LeftNodeType], &mut [Self::RightNodeType]) {
+        let mut right = if !self.keep_columns && self.columns.len() > 1 {
+            self.columns.remove(0)
+        } else {
+            Vec::with_capacity(self.height)
+        };
+        right.clear();
+        right.extend((0..self.height).map(|y| Node::new(y, Score::new(0.0))));
+        self.columns.push(right);
+        let len = self.columns.len();
+        let (left, right) = self.columns.split_at_mut(len - 1);
+        (&left[len - 2], &mut right[0])
+    }
+
+    fn set_parent_of(parent: &Self::LeftNodeType, child: &mut Self::RightNodeType) {
+        child.parent = Some(parent.y);
+    }
+}
+
+impl SimulationSpace {
+    fn new(
+        width: usize,
+        height: usize,
+        max_slope: Option<usize>,
+        constraints: Arc<Constraints>,
+        cost_field: Arc<dyn CostField>,
+    ) -> Self {
+        SimulationSpace {
+            width,
+            height,
+            max_slope,
+            constraints,
+            cost_field,
+            columns: Vec::new(),
+            origin: 0,
+            keep_columns: false,
+        }
+    }
+
+    /// Restarts the simulation at column `origin` with the given aggregated costs.
+    fn seed(&mut self, origin: usize, costs: &[Score], keep_columns: bool) {
+        self.origin = origin;
+        self.keep_columns = keep_columns;
+        self.columns.clear();
+        let constraints = self.constraints.clone();
+        self.columns.push(
+            costs
+                .iter()
+                .enumerate()
+                .map(|(y, &cost)| {
+                    if constraints.allows(origin, y) {
+                        Node::new(y, cost)
+                    } else {
+                        Node::new(y, score::INFINITY)
+                    }
+                })
+                .collect(),
+        );
+    }
+
+    fn last_column_costs(&self) -> Vec<Score> {
+        self.columns
+            .last()
+            .unwrap()
+            .iter()
+            .map(|node| node.aggregated_cost)
+            .collect()
+    }
+}
+
+pub fn checkpoint(options: &SimulationOptions) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+    if options.paths > 1 {
+        return Err("the checkpoint strategy can only find a single trajectory".into());
+    }
+    AllocationData::collect_data(Phase::Setup, None)?;
+    let mut simulation = SimulationSpace::new(
+        options.width,
+        options.height,
+        options.max_slope,
+        options.constraints.clone(),
+        options.cost_field.clone(),
+    );
+    let interval = options
+        .checkpoint_interval
+        .unwrap_or_else(|| (simulation.width as f64).sqrt().ceil() as usize)
+        .max(1);
+
+    let mut checkpoints = vec![options.constraints.start.costs(simulation.height)];
+    simulation.seed(0, &checkpoints[0], false);
+    options.record_costs(0, || simulation.last_column_costs());
+    for x in 1..simulation.width {
+        options.report_column(x);
+        simulation.simulate_par(x);
+        options.record_costs(x, || simulation.last_column_costs());
+        if x % interval == 0 {
+            checkpoints.push(simulation.last_column_costs());
+        }
+        AllocationData::collect_data(Phase::Step, Some(x))?;
+    }
+    options.report_done();
+
+    let last_column = simulation.width - 1;
+    let (cost, mut row) = lowest_minimum(
+        simulation
+            .last_column_costs()
+            .into_iter()
+            .enumerate()
+            .map(|(y, cost)| (options.constraints.final_cost(y, cost), y)),
+    )
+    .unwrap();
+    let cost = check_feasible(cost)?;
+    let mut path = vec![0; simulation.width];
+    path[last_column] = row;
+
+    let mut end = last_column;
+    while end > 0 {
+        let start = (end - 1) / interval * interval;
+        simulation.seed(start, &checkpoints[start / interval], true);
+        for iteration in 1..=end - start {
+            simulation.simulate_par(iteration);
+        }
+        for x in (start + 1..=end).rev() {
+            row = simulation.columns[x - start][row].parent.unwrap();
+            path[x - 1] = row;
+        }
+        checkpoints.truncate(start / interval);
+        AllocationData::collect_data(Phase::Backtrack, Some(start))?;
+        end = start;
+    }
+    drop(checkpoints);
+    drop(simulation);
+
+    AllocationData::collect_data(Phase::Teardown, None)?;
+    Ok(vec![Trajectory::new(cost, path, &*options.cost_field)])
+}
@@ -0,0 +1,245 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs,
+    ops::RangeInclusive,
+    str::FromStr,
+};
+
+use crate::obstacles::{ObstacleMask, StepObstacles};
+use crate::score::{self, Score};
+
+/// Cost of starting or ending the trajectory at each row.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Boundary {
+    /// Every row is allowed at no extra cost.
+    #[default]
+    Free,
+    /// Only the given row is allowed.
+    Row(usize),
+    /// Extra cost of each row, an infinite cost forbids the row.
+    Costs(Vec<Score>),
+}
+
+impl Boundary {
+    pub fn cost(&self, y: usize) -> Score {
+        match self {
+            Boundary::Free => Score::new(0.0),
+            Boundary::Row(row) if *row == y => Score::new(0.0),
+            Boundary::Row(_) => score::INFINITY,
+            Boundary::Costs(costs) => costs[y],
+        }
+    }
+
+    pub fn costs(&self, height: usize) -> Vec<Score> {
+        (0..height).map(|y| self.cost(y)).collect()
+    }
+
+    fn validate(&self, height: usize) -> Result<(), Box<dyn Error>> {
+        match self {
+            Boundary::Row(row) if *row >= height => Err(format!(
+                "row {} is outside of a simulation {} rows high",
+                row, height
+            )
+            .into()),
+            Boundary::Costs(costs) if costs.len() != height => Err(format!(
+                "{} costs given for a simulation {} rows high",
+                costs.len(),
+                height
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses a row index, a comma separated list of costs or a file with whitespace separated costs.
+impl FromStr for Boundary {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(row) = s.parse() {
+            return Ok(Boundary::Row(row));
+        }
+        let costs = if s.contains(',') {
+            s.to_owned()
+        } else {
+            fs::read_to_string(s)?
+        };
+        costs
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|cost| !cost.is_empty())
+            .map(|cost| Score::new_checked(cost.parse()?).ok_or_else(|| "cost is NaN".into()))
+            .collect::<Result<_, _>>()
+            .map(Boundary::Costs)
+    }
+}
+
+/// Rows of a column the trajectory has to pass through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gate {
+    pub column: usize,
+    pub rows: RangeInclusive<usize>,
+}
+
+/// Parses `column:row` waypoints and `column:first-last` gates.
+impl FromStr for Gate {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, rows) = s
+            .split_once(':')
+            .ok_or("expected `column:row` or `column:first-last`")?;
+        let rows = match rows.split_once('-') {
+            Some((first, last)) => first.parse()?..=last.parse()?,
+            None => {
+                let row = rows.parse()?;
+                row..=row
+            }
+        };
+        Ok(Gate {
+            column: column.parse()?,
+            rows,
+        })
+    }
+}
+
+/// Restrictions on where the trajectory may go, shared by every strategy.
+#[derive(Debug, Clone, Default)]
+pub struct Constraints {
+    pub start: Boundary,
+    pub target: Boundary,
+    pub gates: Vec<Gate>,
+    pub obstacles: Option<ObstacleMask>,
+}
+
+impl Constraints {
+    pub fn validate(&self, width: usize, height: usize) -> Result<(), Box<dyn Error>> {
+        self.start.validate(height)?;
+        self.target.validate(height)?;
+        for gate in &self.gates {
+            if gate.column >= width || gate.rows.is_empty() || *gate.rows.end() >= height {
+                return Err(format!(
+                    "gate {}:{}-{} does not fit into a {}x{} simulation",
+                    gate.column,
+                    gate.rows.start(),
+                    gate.rows.end(),
+                    width,
+                    height
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the trajectory may pass through row `y` of column `x`.
+    pub fn allows(&self, x: usize, y: usize) -> bool {
+        self.gates
+            .iter()
+            .filter(|gate| gate.column == x)
+            .all(|gate| gate.rows.contains(&y))
+            && !self
+                .obstacles
+                .as_ref()
+                .is_some_and(|obstacles| obstacles.is_blocked(x, y))
+    }
+
+    /// Obstacles in the way of the edges from column `previous` to column `current`.
+    pub fn step_obstacles(&self, previous: usize, current: usize) -> Option<StepObstacles> {
+        self.obstacles
+            .as_ref()
+            .map(|obstacles| obstacles.step(previous, current))
+    }
+
+    /// Total cost of a trajectory ending at row `y` with the given aggregated cost.
+    pub fn final_cost(&self, y: usize, aggregated_cost: Score) -> Score {
+        (aggregated_cost + self.target.cost(y)).unwrap_or(score::INFINITY)
+    }
+}
+
+/// Fails when the cheapest trajectory found is infinitely expensive.
+pub fn check_feasible(cost: Score) -> Result<Score, InfeasibleError> {
+    if cost.is_finite() {
+        Ok(cost)
+    } else {
+        Err(InfeasibleError)
+    }
+}
+
+/// No trajectory satisfies the constraints.
+#[derive(Clone, Copy)]
+pub struct InfeasibleError;
+
+impl Display for InfeasibleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "no trajectory satisfies the constraints")
+    }
+}
+
+/// `main` reports errors through `Debug`, so it prints the message as well.
+impl fmt::Debug for InfeasibleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Error for InfeasibleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boundary_parses_a_row() {
+        assert_eq!("3".parse::<Boundary>().unwrap(), Boundary::Row(3));
+    }
+
+    #[test]
+    fn boundary_parses_comma_separated_costs() {
+        assert_eq!(
+            "0, 1.5,inf".parse::<Boundary>().unwrap(),
+            Boundary::Costs(vec![Score::new(0.0), Score::new(1.5), score::INFINITY])
+        );
+        assert!("1,NaN".parse::<Boundary>().is_err());
+        assert!("1,x".parse::<Boundary>().is_err());
+    }
+
+    #[test]
+    fn boundary_reads_costs_from_a_file() {
+        let path = std::env::temp_dir().join(format!("boundary-{}.txt", std::process::id()));
+        fs::write(&path, "1 2\n3\n").unwrap();
+        let boundary = path.to_str().unwrap().parse::<Boundary>();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            boundary.unwrap(),
+            Boundary::Costs(vec![Score::new(1.0), Score::new(2.0), Score::new(3.0)])
+        );
+        assert!("/nonexistent/boundary".parse::<Boundary>().is_err());
+    }
+
+    #[test]
+    fn gate_parses_a_waypoint_and_a_range() {
+        assert_eq!(
+            "4:2".parse::<Gate>().unwrap(),
+            Gate {
+                column: 4,
+                rows: 2..=2
+            }
+        );
+        assert_eq!(
+            "4:1-3".parse::<Gate>().unwrap(),
+            Gate {
+                column: 4,
+                rows: 1..=3
+            }
+        );
+    }
+
+    #[test]
+    fn gate_rejects_malformed_input() {
+        for input in ["4", "x:2", "4:", "4:1-", "4:-3", "4:a-b"] {
+            assert!(input.parse::<Gate>().is_err(), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::{error::Error, str::FromStr, sync::Arc};
+
+use noise::{
+    Billow, Fbm, MultiFractal, NoiseFn, OpenSimplex, Perlin, RidgedMulti, Seedable, Worley,
+};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::raster::Raster;
+use crate::score::Score;
+
+/// Cost of the edge between cell `(x0, y0)` and cell `(x1, y1)` of a neighbouring column.
+pub trait CostField: Send + Sync {
+    fn get_cost(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> Score;
+
+    /// Costs of the edges between columns `x0` and `x1`, requested once per simulation step.
+    /// Fields that can share work between the edges of a step precompute it here.
+    fn step(&self, x0: usize, x1: usize) -> Box<dyn StepCosts + '_> {
+        Box::new(DirectStep {
+            field: self,
+            x0,
+            x1,
+        })
+    }
+
+    /// Energy needed to cross cell `(x, y)`, the cost of a unit edge through its center by default.
+    fn cell_energy(&self, x: usize, y: usize) -> f64 {
+        self.get_cost(x, y, x, y).0
+    }
+}
+
+/// Costs of the edges from rows of one column to rows of its neighbour.
+pub trait StepCosts: Sync {
+    fn get_cost(&self, y0: usize, y1: usize) -> Score;
+}
+
+struct DirectStep<'a, F: ?Sized> {
+    field: &'a F,
+    x0: usize,
+    x1: usize,
+}
+
+impl<'a, F: CostField + ?Sized> StepCosts for DirectStep<'a, F> {
+    fn get_cost(&self, y0: usize, y1: usize) -> Score {
+        self.field.get_cost(self.x0, y0, self.x1, y1)
+    }
+}
+
+/// A field whose edge cost is the energy at the midpoint of the edge times its length.
+pub trait MidpointEnergy: Send + Sync {
+    fn energy(&self, x: f64, y: f64) -> f64;
+}
+
+fn midpoint_cost<F: MidpointEnergy>(
+    field: &F,
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+) -> Score {
+    let x = (x0 + x1) as f64 / 2.0;
+    let y = (y0 + y1) as f64 / 2.0;
+    Score::new(field.energy(x, y) * edge_length(y0, y1))
+}
+
+/// Length of the edge between rows `y0` and `y1` of neighbouring columns.
+pub fn edge_length(y0: usize, y1: usize) -> f64 {
+    let y_diff = y1 as f64 - y0 as f64;
+    (y_diff * y_diff + 1.0).sqrt()
+}
+
+/// Noise generators from the `noise` crate that can produce the energy field.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseType {
+    Perlin,
+    Fbm,
+    OpenSimplex,
+    Worley,
+    RidgedMulti,
+    Billow,
+}
+
+impl FromStr for NoiseType {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "perlin" => NoiseType::Perlin,
+            "fbm" => NoiseType::Fbm,
+            "opensimplex" | "open-simplex" => NoiseType::OpenSimplex,
+            "worley" => NoiseType::Worley,
+            "ridgedmulti" | "ridged-multi" => NoiseType::RidgedMulti,
+            "billow" => NoiseType::Billow,
+            _ => return Err(format!("unknown noise type `{}`", s).into()),
+        })
+    }
+}
+
+/// Parameters of the noise energy field.
+#[derive(StructOpt, Debug, Clone, Serialize)]
+pub struct NoiseOptions {
+    /// Noise generator: perlin, fbm, opensimplex, worley, ridgedmulti or billow
+    #[structopt(long = "noise", default_value = "perlin")]
+    pub noise_type: NoiseType,
+    /// Seed of the noise generator
+    #[structopt(long, default_value = "0")]
+    pub seed: u32,
+    /// Octaves of the fbm, ridgedmulti and billow generators
+    #[structopt(long, default_value = "6")]
+    pub octaves: usize,
+    /// Frequency of the generator, for perlin and opensimplex it multiplies the noise scale
+    #[structopt(long, default_value = "1.0")]
+    pub frequency: f64,
+    /// Frequency multiplier between octaves of the fbm, ridgedmulti and billow generators
+    #[structopt(long, default_value = "2.0943951023931953")]
+    pub lacunarity: f64,
+    /// Noise periods across the width and the height of the grid
+    #[structopt(long, default_value = "6.0")]
+    pub noise_scale: f64,
+    /// Energy added to the noise value, keeps the cost positive
+    #[structopt(long, default_value = "1.05")]
+    pub base_energy: f64,
+}
+
+/// Same as the command line defaults.
+impl Default for NoiseOptions {
+    fn default() -> Self {
+        NoiseOptions {
+            noise_type: NoiseType::Perlin,
+            seed: 0,
+            octaves: 6,
+            frequency: 1.0,
+            lacunarity: Fbm::DEFAULT_LACUNARITY,
+            noise_scale: 6.0,
+            base_energy: 1.05,
+        }
+    }
+}
+
+impl NoiseOptions {
+    fn generator(&self) -> Box<dyn NoiseFn<[f64; 2]> + Send + Sync> {
+        match self.noise_type {
+            NoiseType::Perlin => Box::new(Perlin::new().set_seed(self.seed)),
+            NoiseType::OpenSimplex => Box::new(OpenSimplex::new().set_seed(self.seed)),
+            NoiseType::Worley => Box::new(
+                Worley::new()
+                    .set_seed(self.seed)
+                    .set_frequency(self.frequency),
+            ),
+            NoiseType::Fbm => Box::new(self.fractal(Fbm::new())),
+            NoiseType::RidgedMulti => Box::new(self.fractal(RidgedMulti::new())),
+            NoiseType::Billow => Box::new(self.fractal(Billow::new())),
+        }
+    }
+
+    fn fractal<F: MultiFractal + Seedable>(&self, fractal: F) -> F {
+        fractal
+            .set_seed(self.seed)
+            .set_octaves(self.octaves)
+            .set_frequency(self.frequency)
+            .set_lacunarity(self.lacunarity)
+    }
+
+    /// Scale of the noise input, generators without a frequency of their own get it here.
+    fn input_scale(&self) -> f64 {
+        match self.noise_type {
+            NoiseType::Perlin | NoiseType::OpenSimplex => self.noise_scale * self.frequency,
+            _ => self.noise_scale,
+        }
+    }
+}
+
+/// Energy sampled from a noise generator at the midpoint of the edge, times the edge length.
+pub struct NoiseCostField {
+    width: usize,
+    height: usize,
+    input_scale: f64,
+    base_energy: f64,
+    noise: Box<dyn NoiseFn<[f64; 2]> + Send + Sync>,
+}
+
+impl NoiseCostField {
+    pub fn new(width: usize, height: usize, options: &NoiseOptions) -> Self {
+        NoiseCostField {
+            width,
+            height,
+            input_scale: options.input_scale(),
+            base_energy: options.base_energy,
+            noise: options.generator(),
+        }
+    }
+}
+
+impl MidpointEnergy for NoiseCostField {
+    fn energy(&self, x: f64, y: f64) -> f64 {
+        self.base_energy
+            + self.noise.get([
+                x / self.width as f64 * self.input_scale,
+                y / self.height as f64 * self.input_scale,
+            ])
+    }
+}
+
+impl CostField for NoiseCostField {
+    fn get_cost(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> Score {
+        midpoint_cost(self, x0, y0, x1, y1)
+    }
+}
+
+/// Energy read from a grayscale image stretched over the grid, brighter pixels need more energy.
+/// The image is bilinearly sampled at the midpoint of the edge.
+pub struct HeightmapCostField {
+    width: usize,
+    height: usize,
+    heightmap: Raster,
+}
+
+impl HeightmapCostField {
+    pub fn new(width: usize, height: usize, heightmap: Raster) -> Self {
+        HeightmapCostField {
+            width,
+            height,
+            heightmap,
+        }
+    }
+}
+
+impl MidpointEnergy for HeightmapCostField {
+    fn energy(&self, x: f64, y: f64) -> f64 {
+        self.heightmap.sample_bilinear(
+            (x + 0.5) / self.width as f64 * self.heightmap.width as f64,
+            (y + 0.5) / self.height as f64 * self.heightmap.height as f64,
+        )
+    }
+}
+
+impl CostField for HeightmapCostField {
+    fn get_cost(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> Score {
+        midpoint_cost(self, x0, y0, x1, y1)
+    }
+}
+
+/// Evaluates the energy of a step once per half row in parallel, instead of once per edge.
+/// Edges between neighbouring columns share their midpoint whenever `y0 + y1` is the same,
+/// so a step needs only `2 * height - 1` samples while it has up to `height²` edges.
+pub struct CachedCostField<F> {
+    height: usize,
+    field: F,
+}
+
+impl<F: MidpointEnergy> CachedCostField<F> {
+    pub fn new(height: usize, field: F) -> Self {
+        CachedCostField { height, field }
+    }
+}
+
+impl<F: MidpointEnergy> CostField for CachedCostField<F> {
+    fn get_cost(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> Score {
+        midpoint_cost(&self.field, x0, y0, x1, y1)
+    }
+
+    fn step(&self, x0: usize, x1: usize) -> Box<dyn StepCosts + '_> {
+        let x = (x0 + x1) as f64 / 2.0;
+        let mut energies = Vec::new();
+        (0..2 * self.height - 1)
+            .into_par_iter()
+            .map(|half_row| self.field.energy(x, half_row as f64 / 2.0))
+            .collect_into_vec(&mut energies);
+        Box::new(CachedStep { energies })
+    }
+}
+
+/// Energies indexed by `y0 + y1`.
+struct CachedStep {
+    energies: Vec<f64>,
+}
+
+impl StepCosts for CachedStep {
+    fn get_cost(&self, y0: usize, y1: usize) -> Score {
+        Score::new(self.energies[y0 + y1] * edge_length(y0, y1))
+    }
+}
+
+/// Wraps the field into a `CachedCostField` when `cache` is set.
+pub fn cache_if<F: CostField + MidpointEnergy + 'static>(
+    cache: bool,
+    height: usize,
+    field: F,
+) -> Arc<dyn CostField> {
+    if cache {
+        Arc::new(CachedCostField::new(height, field))
+    } else {
+        Arc::new(field)
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Number of size classes, class `i` holds the requests of `2^(i-1) + 1..=2^i` bytes.
+pub const SIZE_CLASSES: usize = usize::BITS as usize + 1;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = ZERO;
+static DEALLOCATIONS: AtomicUsize = ZERO;
+static REALLOCATIONS: AtomicUsize = ZERO;
+static BYTES_ALLOCATED: AtomicUsize = ZERO;
+static BYTES_FREED: AtomicUsize = ZERO;
+static HISTOGRAM: [AtomicUsize; SIZE_CLASSES] = [ZERO; SIZE_CLASSES];
+
+/// Wraps the global allocator and counts the calls made to it, so that the profiler can tell
+/// many small allocations from a few large ones. The counters stay zero unless it is installed
+/// with `#[global_allocator]`.
+pub struct CountingAllocator<A>(pub A);
+
+/// Totals since the start of the process. A reallocation counts as freeing the old size and
+/// allocating the new one, so `bytes_allocated - bytes_freed` is the size of the live blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationCounts {
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub reallocations: usize,
+    pub bytes_allocated: usize,
+    pub bytes_freed: usize,
+}
+
+pub fn counts() -> AllocationCounts {
+    AllocationCounts {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+        reallocations: REALLOCATIONS.load(Ordering::Relaxed),
+        bytes_allocated: BYTES_ALLOCATED.load(Ordering::Relaxed),
+        bytes_freed: BYTES_FREED.load(Ordering::Relaxed),
+    }
+}
+
+/// Allocations and reallocations by the largest size of their class, empty classes left out.
+pub fn size_classes() -> Vec<(usize, usize)> {
+    HISTOGRAM
+        .iter()
+        .enumerate()
+        .map(|(class, count)| (class_limit(class), count.load(Ordering::Relaxed)))
+        .filter(|&(_, count)| count > 0)
+        .collect()
+}
+
+fn size_class(size: usize) -> usize {
+    (usize::BITS - size.saturating_sub(1).leading_zeros()) as usize
+}
+
+fn class_limit(class: usize) -> usize {
+    1usize.checked_shl(class as u32).unwrap_or(usize::MAX)
+}
+
+fn record_allocation(ptr: *mut u8, size: usize) -> *mut u8 {
+    if !ptr.is_null() {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES_ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        HISTOGRAM[size_class(size)].fetch_add(1, Ordering::Relaxed);
+    }
+    ptr
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation(self.0.alloc(layout), layout.size())
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record_allocation(self.0.alloc_zeroed(layout), layout.size())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES_FREED.fetch_add(layout.size(), Ordering::Relaxed);
+        self.0.dealloc(ptr, layout)
+    }
+
+    /// Failed reallocations keep the old block and are not counted.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.0.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            REALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            BYTES_FREED.fetch_add(layout.size(), Ordering::Relaxed);
+            BYTES_ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            HISTOGRAM[size_class(new_size)].fetch_add(1, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
@@ -0,0 +1,246 @@
+use std::error::Error;
+
+use crate::constraints::{check_feasible, Constraints};
+use crate::cost_field::CostField;
+use crate::memory_profiler::{AllocationData, Phase};
+use crate::score::{self, Score};
+use crate::simulation::{lowest_minimum, LeftNode, RightNode};
+use crate::simulation::{Simulation, SimulationOptions};
+use crate::trajectory::Trajectory;
+
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+struct Node {
+    aggregated_cost: Score,
+    /// Row in which the cheapest trajectory reaching the node passes the middle column of the sweep.
+    middle_row: usize,
+}
+
+impl LeftNode for Node {
+    fn aggregated_cost(&self) -> Score {
+        self.aggregated_cost
+    }
+}
+impl RightNode for Node {
+    fn set_aggregated_cost(&mut self, score: Score) {
+        self.aggregated_cost = score;
+    }
+    fn aggregated_cost(&self) -> Score {
+        self.aggregated_cost
+    }
+}
+
+impl Node {
+    const fn new(aggregated_cost: Score) -> Self {
+        Node {
+            aggregated_cost,
+            middle_row: 0,
+        }
+    }
+}
+
+/// Holds only the two columns of the sweep that is currently running, which starts at column `origin`.
+struct SimulationSpace {
+    width: usize,
+    height: usize,
+    max_slope: Option<usize>,
+    constraints: Arc<Constraints>,
+    cost_field: Arc<dyn CostField>,
+    previous: Vec<Node>,
+    current: Vec<Node>,
+    origin: usize,
+}
+
+impl Simulation for SimulationSpace {
+    type LeftNodeType = Node;
+    type RightNodeType = Node;
+
+    fn get_cost_field(&self) -> Arc<dyn CostField> {
+        self.cost_field.clone()
+    }
+
+    fn max_slope(&self) -> Option<usize> {
+        self.max_slope
+    }
+
+    fn constraints(&self) -> Arc<Constraints> {
+        self.constraints.clone()
+    }
+
+    fn column(&self, iteration: usize) -> usize {
+        self.origin + iteration
+    }
+
+    fn prepare_step_slices(
+        &mut self,
+        _: usize,
+    ) -> (&[Self::LeftNodeType], &mut [Self::RightNodeType]) {
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+        self.current
+            .extend((0..self.height).map(|_| Node::new(Score::new(0.0))));
+
+        (&self.previous[..], &mut self.current[..])
+    }
+
+    fn set_parent_of(parent: &Self::LeftNodeType, child: &mut Self::RightNodeType) {
+        child.middle_row = parent.middle_row;
+    }
+}
+
+impl SimulationSpace {
+    fn new(
+        width: usize,
+        height: usize,
+        max_slope: Option<usize>,
+        constraints: Arc<Constraints>,
+        cost_field: Arc<dyn CostField>,
+    ) -> Self {
+        SimulationSpace {
+            width,
+            height,
+            max_slope,
+            constraints,
+            cost_field,
+            previous: Vec::with_capacity(height),
+            current: Vec::with_capacity(height),
+            origin: 0,
+        }
+    }
+
+    /// Runs the dynamic programming from column `from` (seeded with the `initial` costs) to column `to`.
+    fn sweep(&mut self, from: usize, to: usize, initial: Border) {
+        self.origin = from;
+        self.current.clear();
+        let constraints = self.constraints.clone();
+        self.current.extend((0..self.height).map(|y| {
+            if constraints.allows(from, y) {
+                Node::new(initial.cost(y))
+            } else {
+                Node::new(score::INFINITY)
+            }
+        }));
+        self.resume(from, to);
+    }
+
+    /// Continues the running sweep, which has reached column `from`, up to column `to`.
+    fn resume(&mut self, from: usize, to: usize) {
+        for iteration in from - self.origin + 1..=to - self.origin {
+            self.simulate_par(iteration);
+        }
+    }
+
+    /// Lowest row of the current column minimizing its aggregated cost plus the `end` cost,
+    /// together with the minimum.
+    fn best_row(&self, end: Border) -> (Score, usize) {
+        lowest_minimum(
+            self.current
+                .iter()
+                .enumerate()
+                .filter_map(|(y, node)| Some(((node.aggregated_cost + end.cost(y))?, y))),
+        )
+        .unwrap()
+    }
+
+    /// Writes into `path[a..=b]` the cheapest trajectory between columns `a` and `b`
+    /// and returns its cost, where `start` holds the column the sweep starts in, `a` or the one
+    /// before, with its costs and `end` the costs of leaving column `b` at each row.
+    ///
+    /// The middle row is the one the trajectory found by backtracking from column `b` passes,
+    /// so that ties are broken as by the other strategies. The sweep from `a` to `b` carries
+    /// it along, and the right half starts from the aggregated cost of the middle column,
+    /// so that both halves sum up the same costs in the same order as one forward pass.
+    /// Only that row and cost are kept across the recursion, so a level takes constant memory.
+    fn solve(
+        &mut self,
+        options: &SimulationOptions,
+        start: (usize, Border),
+        a: usize,
+        b: usize,
+        end: Border,
+        path: &mut [usize],
+    ) -> Result<Score, Box<dyn Error>> {
+        let (from, initial) = start;
+        if a == b {
+            self.sweep(from, a, initial);
+            let (cost, row) = self.best_row(end);
+            path[a] = row;
+            Ok(check_feasible(cost)?)
+        } else {
+            let middle = (a + b) / 2;
+            self.sweep(from, middle, initial);
+            let middle_costs: Vec<Score> = self
+                .current
+                .iter_mut()
+                .enumerate()
+                .map(|(y, node)| {
+                    node.middle_row = y;
+                    node.aggregated_cost
+                })
+                .collect();
+            self.resume(middle, b);
+            let (cost, row) = self.best_row(end);
+            check_feasible(cost)?;
+            let row = self.current[row].middle_row;
+            let middle_cost = middle_costs[row];
+            drop(middle_costs);
+            options.report_column(middle);
+            AllocationData::collect_data(Phase::Step, Some(middle))?;
+
+            let through_row = Border::Fixed(row, Score::new(0.0));
+            self.solve(options, start, a, middle, through_row, path)?;
+            let from_row = (middle, Border::Fixed(row, middle_cost));
+            self.solve(options, from_row, middle + 1, b, end, path)?;
+            Ok(cost)
+        }
+    }
+}
+
+/// Costs of entering or leaving a column at each row.
+#[derive(Clone, Copy)]
+enum Border<'a> {
+    Rows(&'a [Score]),
+    /// Forces the trajectory through a single row, where it costs the given score.
+    Fixed(usize, Score),
+}
+
+impl Border<'_> {
+    fn cost(&self, y: usize) -> Score {
+        match *self {
+            Border::Rows(costs) => costs[y],
+            Border::Fixed(row, cost) if row == y => cost,
+            Border::Fixed(..) => score::INFINITY,
+        }
+    }
+}
+
+pub fn hirschberg(options: &SimulationOptions) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+    if options.paths > 1 {
+        return Err("the hirschberg strategy can only find a single trajectory".into());
+    }
+    AllocationData::collect_data(Phase::Setup, None)?;
+    let mut simulation = SimulationSpace::new(
+        options.width,
+        options.height,
+        options.max_slope,
+        options.constraints.clone(),
+        options.cost_field.clone(),
+    );
+    let start = options.constraints.start.costs(simulation.height);
+    let target = options.constraints.target.costs(simulation.height);
+    let mut path = vec![0; simulation.width];
+    let cost = simulation.solve(
+        options,
+        (0, Border::Rows(&start)),
+        0,
+        simulation.width - 1,
+        Border::Rows(&target),
+        &mut path,
+    )?;
+    options.report_done();
+    drop(simulation);
+
+    AllocationData::collect_data(Phase::Teardown, None)?;
+    Ok(vec![Trajectory::new(cost, path, &*options.cost_field)])
+}
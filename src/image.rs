@@ -0,0 +1,385 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+
+use crate::constraints::Constraints;
+use crate::cost_field::CostField;
+use crate::score::{self, Score};
+use crate::simulation::{LeftNode, RightNode, Simulation, SimulationOptions};
+use crate::trajectory::Trajectory;
+
+/// What the pixels under the trajectories show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageLayer {
+    /// Energy needed to cross each cell.
+    Energy,
+    /// Aggregated cost of the cheapest trajectory reaching each cell, scaled per column.
+    Cost,
+}
+
+/// The cost layer as the strategies compute it, one column at a time. Columns are scaled
+/// between their cheapest and their most expensive reachable cell as they arrive, so only
+/// a byte per cell is kept.
+pub struct ColumnCosts {
+    height: usize,
+    /// Column-major palette indices.
+    pixels: Mutex<Vec<u8>>,
+    recorded: Vec<AtomicBool>,
+}
+
+impl ColumnCosts {
+    pub fn new(width: usize, height: usize) -> Self {
+        ColumnCosts {
+            height,
+            pixels: Mutex::new(vec![UNREACHABLE; width * height]),
+            recorded: (0..width).map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+
+    /// Stores the aggregated costs of column `x`, recording a column again overwrites it.
+    pub fn record(&self, x: usize, costs: impl IntoIterator<Item = Score>) {
+        let costs: Vec<_> = costs.into_iter().collect();
+        let reachable = || {
+            costs
+                .iter()
+                .map(|cost| cost.0)
+                .filter(|cost| cost.is_finite())
+        };
+        let min = reachable().fold(f64::INFINITY, f64::min);
+        let max = reachable().fold(f64::NEG_INFINITY, f64::max);
+        let mut pixels = self.pixels.lock().unwrap();
+        let column = &mut pixels[x * self.height..(x + 1) * self.height];
+        for (pixel, cost) in column.iter_mut().zip(&costs) {
+            *pixel = if cost.is_finite() {
+                ramp_index(cost.0, min, max)
+            } else {
+                UNREACHABLE
+            };
+        }
+        self.recorded[x].store(true, Ordering::Relaxed);
+    }
+
+    /// Whether every column has been recorded. Strategies without a full forward pass,
+    /// like `hirschberg`, record none.
+    pub fn is_complete(&self) -> bool {
+        self.recorded
+            .iter()
+            .all(|recorded| recorded.load(Ordering::Relaxed))
+    }
+}
+
+/// Palette entries `0..=RAMP_MAX` are the heatmap, from cheap to expensive.
+const RAMP_MAX: u8 = 252;
+const UNREACHABLE: u8 = 253;
+const ALTERNATIVE_PATH: u8 = 254;
+const PATH: u8 = 255;
+
+/// Viridis, sampled at five evenly spaced points.
+const RAMP: [[f64; 3]; 5] = [
+    [68.0, 1.0, 84.0],
+    [59.0, 82.0, 139.0],
+    [33.0, 145.0, 140.0],
+    [94.0, 201.0, 98.0],
+    [253.0, 231.0, 37.0],
+];
+
+/// Heatmap color of `t` in `0.0..=1.0`.
+pub(crate) fn ramp_color(t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0) * (RAMP.len() - 1) as f64;
+    let low = (t.floor() as usize).min(RAMP.len() - 2);
+    let fraction = t - low as f64;
+    let mut color = [0; 3];
+    for (channel, value) in color.iter_mut().enumerate() {
+        let (a, b) = (RAMP[low][channel], RAMP[low + 1][channel]);
+        *value = (a + (b - a) * fraction).round() as u8;
+    }
+    color
+}
+
+fn palette() -> Vec<[u8; 3]> {
+    let mut palette: Vec<_> = (0..=RAMP_MAX)
+        .map(|index| ramp_color(index as f64 / RAMP_MAX as f64))
+        .collect();
+    palette.push([0, 0, 0]);
+    palette.push([255, 255, 255]);
+    palette.push([255, 0, 0]);
+    palette
+}
+
+fn ramp_index(value: f64, min: f64, max: f64) -> u8 {
+    if max > min {
+        ((value - min) / (max - min) * RAMP_MAX as f64).round() as u8
+    } else {
+        0
+    }
+}
+
+/// Writes the layer at one pixel per cell, with the first trajectory drawn in red and the
+/// others in white over it. Blocked and unreachable cells are black.
+/// The file is a binary PPM if the path ends with `.ppm` and an indexed PNG otherwise.
+/// Rows are produced and written one at a time, so only the cost layer keeps a byte per cell.
+/// The cost layer comes from `options.column_costs` if the strategy filled it, from a forward
+/// pass of its own otherwise.
+pub fn write_image(
+    path: &str,
+    layer: ImageLayer,
+    options: &SimulationOptions,
+    trajectories: &[Trajectory],
+) -> Result<(), Box<dyn Error>> {
+    let pixels: Box<dyn Layer> = match layer {
+        ImageLayer::Energy => Box::new(EnergyLayer::new(options)),
+        ImageLayer::Cost => Box::new(CostLayer::new(options)),
+    };
+    let overlay = Overlay::new(trajectories, options.width);
+    let out = BufWriter::new(File::create(path)?);
+    let (width, height) = (options.width, options.height);
+    let rows = (0..height).map(|y| {
+        let mut row = vec![0; width];
+        pixels.row(y, &mut row);
+        overlay.draw(y, &mut row);
+        row
+    });
+    if path.ends_with(".ppm") {
+        write_ppm(out, width, height, rows)?;
+    } else {
+        write_png(out, width, height, rows)?;
+    }
+    Ok(())
+}
+
+fn write_ppm(
+    mut out: impl Write,
+    width: usize,
+    height: usize,
+    rows: impl Iterator<Item = Vec<u8>>,
+) -> io::Result<()> {
+    let palette = palette();
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    for row in rows {
+        let rgb: Vec<u8> = row
+            .iter()
+            .flat_map(|&index| palette[index as usize].iter().copied())
+            .collect();
+        out.write_all(&rgb)?;
+    }
+    out.flush()
+}
+
+fn write_png(
+    out: impl Write,
+    width: usize,
+    height: usize,
+    rows: impl Iterator<Item = Vec<u8>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette().concat());
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer();
+    for row in rows {
+        stream.write_all(&row)?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+trait Layer {
+    /// Palette indices of row `y`.
+    fn row(&self, y: usize, out: &mut [u8]);
+}
+
+/// Samples the cost field twice, once for its range and once per written row.
+struct EnergyLayer {
+    cost_field: Arc<dyn CostField>,
+    constraints: Arc<Constraints>,
+    min: f64,
+    max: f64,
+}
+
+impl EnergyLayer {
+    fn new(options: &SimulationOptions) -> Self {
+        let cost_field = &options.cost_field;
+        let width = options.width;
+        let (min, max) = (0..options.height)
+            .into_par_iter()
+            .map(|y| {
+                (0..width)
+                    .map(|x| cost_field.cell_energy(x, y))
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), energy| {
+                        (min.min(energy), max.max(energy))
+                    })
+            })
+            .reduce(
+                || (f64::INFINITY, f64::NEG_INFINITY),
+                |(min0, max0), (min1, max1)| (min0.min(min1), max0.max(max1)),
+            );
+        EnergyLayer {
+            cost_field: options.cost_field.clone(),
+            constraints: options.constraints.clone(),
+            min,
+            max,
+        }
+    }
+}
+
+impl Layer for EnergyLayer {
+    fn row(&self, y: usize, out: &mut [u8]) {
+        out.par_iter_mut().enumerate().for_each(|(x, pixel)| {
+            *pixel = if self.constraints.allows(x, y) {
+                ramp_index(self.cost_field.cell_energy(x, y), self.min, self.max)
+            } else {
+                UNREACHABLE
+            };
+        });
+    }
+}
+
+/// The recorded costs, or those of a forward pass over the grid that keeps only two columns.
+struct CostLayer {
+    column_costs: Arc<ColumnCosts>,
+}
+
+impl CostLayer {
+    fn new(options: &SimulationOptions) -> Self {
+        let recorded = options
+            .column_costs
+            .clone()
+            .filter(|column_costs| column_costs.is_complete());
+        let column_costs = match recorded {
+            Some(column_costs) => column_costs,
+            None => {
+                let column_costs = Arc::new(ColumnCosts::new(options.width, options.height));
+                let mut pass = CostPass {
+                    height: options.height,
+                    max_slope: options.max_slope,
+                    constraints: options.constraints.clone(),
+                    cost_field: options.cost_field.clone(),
+                    previous: Vec::new(),
+                    current: Vec::new(),
+                };
+                for x in 0..options.width {
+                    pass.simulate_par(x);
+                    column_costs.record(x, pass.current.iter().map(|cell| cell.0));
+                }
+                column_costs
+            }
+        };
+        CostLayer { column_costs }
+    }
+}
+
+impl Layer for CostLayer {
+    fn row(&self, y: usize, out: &mut [u8]) {
+        let height = self.column_costs.height;
+        let pixels = self.column_costs.pixels.lock().unwrap();
+        for (x, pixel) in out.iter_mut().enumerate() {
+            *pixel = pixels[x * height + y];
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Cell(Score);
+
+impl LeftNode for Cell {
+    fn aggregated_cost(&self) -> Score {
+        self.0
+    }
+}
+
+impl RightNode for Cell {
+    fn set_aggregated_cost(&mut self, score: Score) {
+        self.0 = score;
+    }
+    fn aggregated_cost(&self) -> Score {
+        self.0
+    }
+}
+
+struct CostPass {
+    height: usize,
+    max_slope: Option<usize>,
+    constraints: Arc<Constraints>,
+    cost_field: Arc<dyn CostField>,
+    previous: Vec<Cell>,
+    current: Vec<Cell>,
+}
+
+impl Simulation for CostPass {
+    type LeftNodeType = Cell;
+    type RightNodeType = Cell;
+
+    fn prepare_step_slices(
+        &mut self,
+        _: usize,
+    ) -> (&[Self::LeftNodeType], &mut [Self::RightNodeType]) {
+        let current = vec![Cell(score::INFINITY); self.height];
+        self.previous = std::mem::replace(&mut self.current, current);
+        (&self.previous, &mut self.current)
+    }
+
+    fn get_cost_field(&self) -> Arc<dyn CostField> {
+        self.cost_field.clone()
+    }
+
+    fn max_slope(&self) -> Option<usize> {
+        self.max_slope
+    }
+
+    fn constraints(&self) -> Arc<Constraints> {
+        self.constraints.clone()
+    }
+
+    fn set_parent_of(_: &Self::LeftNodeType, _: &mut Self::RightNodeType) {}
+}
+
+/// Rows covered by each trajectory in every column. Like the obstacle test, a column holds
+/// the cells between its own row and the midpoints of the edges to its neighbours,
+/// so that steep steps stay connected.
+struct Overlay {
+    /// `(color, first row, last row)` per column, drawn in order.
+    spans: Vec<Vec<(u8, usize, usize)>>,
+}
+
+impl Overlay {
+    fn new(trajectories: &[Trajectory], width: usize) -> Self {
+        let mut spans = vec![Vec::new(); width];
+        for (i, trajectory) in trajectories.iter().enumerate().rev() {
+            let color = if i == 0 { PATH } else { ALTERNATIVE_PATH };
+            let rows = &trajectory.rows;
+            let mut covered: Vec<_> = rows.iter().map(|&y| (y, y)).collect();
+            for x in 1..rows.len() {
+                let (y0, y1) = (rows[x - 1], rows[x]);
+                let sum = y0 + y1;
+                let (low, high) = if y0 <= y1 { (x - 1, x) } else { (x, x - 1) };
+                covered[low].1 = covered[low].1.max(sum / 2);
+                covered[high].0 = covered[high].0.min(sum.div_ceil(2));
+            }
+            for (column, (first, last)) in spans.iter_mut().zip(covered) {
+                column.push((color, first, last));
+            }
+        }
+        Overlay { spans }
+    }
+
+    fn draw(&self, y: usize, row: &mut [u8]) {
+        for (pixel, column) in row.iter_mut().zip(&self.spans) {
+            for &(color, first, last) in column {
+                if (first..=last).contains(&y) {
+                    *pixel = color;
+                }
+            }
+        }
+    }
+}
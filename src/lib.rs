@@ -0,0 +1,62 @@
+#![feature(const_float_classify)]
+#![feature(const_panic)]
+
+use simulation::SimulationOptions;
+use std::{collections::HashMap, error::Error};
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod allocator;
+pub mod brute_force;
+mod checkpoint;
+pub mod constraints;
+pub mod cost_field;
+pub mod counting_allocator;
+mod hirschberg;
+pub mod image;
+mod linear;
+pub mod memory_profiler;
+mod naive;
+pub mod obstacles;
+pub mod path_export;
+pub mod raster;
+mod reference_count;
+mod reference_count_plus;
+pub mod score;
+pub mod simulation;
+pub mod svg;
+mod trajectory;
+mod utils;
+pub mod verify;
+
+pub use trajectory::{Trajectory, TrajectoryBuilder};
+
+pub type SimulationFunc = fn(&SimulationOptions) -> Result<Vec<Trajectory>, Box<dyn Error>>;
+
+lazy_static! {
+    pub static ref SIMULATIONS: HashMap<&'static str, SimulationFunc> = vec![
+        ("rc", reference_count::reference_count as SimulationFunc),
+        (
+            "rc+",
+            reference_count_plus::reference_count_plus as SimulationFunc
+        ),
+        ("naive", naive::naive as SimulationFunc),
+        ("linear", linear::linear as SimulationFunc),
+        ("hirschberg", hirschberg::hirschberg as SimulationFunc),
+        ("checkpoint", checkpoint::checkpoint as SimulationFunc),
+    ]
+    .into_iter()
+    .collect();
+}
+
+/// Runs the strategy registered under `name`.
+pub fn simulate(
+    name: &str,
+    options: &SimulationOptions,
+) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+    let simulation = SIMULATIONS
+        .get(name)
+        .ok_or_else(|| format!("unknown simulation type `{}`", name))?;
+    simulation(options)
+}
@@ -1,27 +1,33 @@
-#![feature(const_float_classify)]
-#![feature(const_panic)]
-
-use std::{borrow::Borrow, collections::HashMap, error::Error};
+use std::{env, error::Error, fs::File, time::Duration};
 use structopt::StructOpt;
+use sweep::SweepOptions;
+use trajectory_calculator::{
+    allocator::{self, Backend, SelectableAllocator},
+    constraints::{Boundary, Gate},
+    cost_field::NoiseOptions,
+    counting_allocator::CountingAllocator,
+    image::{self, ImageLayer},
+    memory_profiler::{AllocationData, RunMetadata, Sampler},
+    obstacles::ObstacleMask,
+    path_export::{self, PathHeader},
+    raster::Raster,
+    simulate,
+    svg::{self, SvgOptions},
+    verify::verify,
+    Trajectory, TrajectoryBuilder,
+};
 
-#[macro_use]
-extern crate lazy_static;
-
-mod linear;
-mod memory_profiler;
-mod naive;
-mod reference_count;
-mod reference_count_plus;
-mod score;
-mod simulation;
-mod utils;
+mod sweep;
 
 #[global_allocator]
-static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
+static ALLOC: CountingAllocator<SelectableAllocator> = CountingAllocator(SelectableAllocator);
 
 #[derive(StructOpt, Debug)]
-#[structopt()]
+#[structopt(
+    after_help = "The allocator is chosen with the TRAJECTORY_ALLOCATOR environment variable: jemalloc (default), system or bump."
+)]
 struct ProgramOptions {
+    /// Strategy, or `all` to run every strategy and check that they find the same trajectory
     #[structopt(short = "t", long, default_value = "naive")]
     simulation_type: String,
     #[structopt(short = "o", long, default_value = "/dev/null")]
@@ -30,31 +36,167 @@ struct ProgramOptions {
     width: usize,
     #[structopt(short = "y", long, default_value = "16")]
     height: usize,
+    /// Maximal row change between neighbouring columns (unbounded by default)
+    #[structopt(short = "s", long)]
+    max_slope: Option<usize>,
+    /// Columns between stored checkpoints of the `checkpoint` strategy (sqrt of width by default)
+    #[structopt(short = "c", long)]
+    checkpoint_interval: Option<usize>,
+    /// Number of cheapest distinct trajectories to find
+    #[structopt(short = "k", long, default_value = "1")]
+    paths: usize,
+    /// Start row, comma separated start costs or a file with one start cost per row
+    #[structopt(long)]
+    start: Option<Boundary>,
+    /// Target row, comma separated terminal costs or a file with one terminal cost per row
+    #[structopt(long)]
+    target: Option<Boundary>,
+    /// Waypoint `column:row` or gate `column:first-last` the trajectory has to pass, may be repeated
+    #[structopt(short = "w", long = "waypoint")]
+    waypoints: Vec<Gate>,
+    /// PBM/PGM image or text grid (`#` blocked, `.` free) of cells the trajectory has to avoid
+    #[structopt(long)]
+    obstacles: Option<String>,
+    /// PGM or 8/16 bit grayscale PNG whose brightness is the energy needed to cross each cell,
+    /// noise is used otherwise
+    #[structopt(long)]
+    heightmap: Option<String>,
+    #[structopt(flatten)]
+    noise: NoiseOptions,
+    /// Evaluate the energy field once per half row of each step instead of once per edge
+    #[structopt(long)]
+    cache_costs: bool,
+    /// Writes the energy field at one pixel per cell with the trajectories on top, PPM if the
+    /// name ends with `.ppm`, PNG otherwise
+    #[structopt(long)]
+    image: Option<String>,
+    /// Like `--image`, with the aggregated costs scaled per column under the trajectories
+    #[structopt(long)]
+    cost_image: Option<String>,
+    /// Writes the trajectories over contour lines of the energy field as SVG
+    #[structopt(long = "svg")]
+    svg_file: Option<String>,
+    #[structopt(flatten)]
+    svg: SvgOptions,
+    /// Writes the trajectories step by step with their costs, CSV if the name ends with `.csv`,
+    /// JSON otherwise
+    #[structopt(long)]
+    path_out: Option<String>,
+    /// Milliseconds between samples of a background thread that records the peak memory use
+    /// between the samples taken after each column
+    #[structopt(long, parse(try_from_str = parse_sample_interval))]
+    sample_interval: Option<Duration>,
+    /// Adds active, mapped, metadata and retained memory and the fragmentation of the allocator
+    /// arenas to every memory sample
+    #[structopt(long)]
+    extended_stats: bool,
     #[structopt(short, long)]
     debug: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
-type SimulationFunc = fn(String, usize, usize, bool) -> Result<(), Box<dyn Error>>;
-
-lazy_static! {
-    static ref SIMULATIONS: HashMap<&'static str, SimulationFunc> = vec![
-        ("rc", reference_count::reference_count as SimulationFunc),
-        (
-            "rc+",
-            reference_count_plus::reference_count_plus as SimulationFunc
-        ),
-        ("naive", naive::naive as SimulationFunc),
-        ("linear", linear::linear as SimulationFunc),
-    ]
-    .into_iter()
-    .collect();
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    Sweep(SweepOptions),
 }
+
+/// A zero interval would keep the sampler thread polling without pause.
+fn parse_sample_interval(millis: &str) -> Result<Duration, Box<dyn Error>> {
+    match millis.parse()? {
+        0 => Err("the sample interval has to be at least one millisecond".into()),
+        millis => Ok(Duration::from_millis(millis)),
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let opts = ProgramOptions::from_args();
+    if let Ok(name) = env::var(allocator::ALLOCATOR_VAR) {
+        name.parse::<Backend>()?;
+    }
+    if let Some(Command::Sweep(sweep)) = opts.command {
+        return sweep::sweep(sweep);
+    }
+    AllocationData::enable();
+    if opts.extended_stats {
+        if allocator::active() != Some(Backend::Jemalloc) {
+            return Err("--extended-stats needs the jemalloc allocator".into());
+        }
+        AllocationData::enable_extended_stats();
+    }
+    let sampler = opts.sample_interval.map(Sampler::start);
+    let header = PathHeader {
+        strategy: opts.simulation_type.clone(),
+        width: opts.width,
+        height: opts.height,
+        max_slope: opts.max_slope,
+        noise: match opts.heightmap {
+            Some(_) => None,
+            None => Some(opts.noise.clone()),
+        },
+        heightmap: opts.heightmap.clone(),
+    };
+    let metadata = RunMetadata {
+        strategy: opts.simulation_type.clone(),
+        width: opts.width,
+        height: opts.height,
+        seed: header.noise.as_ref().map(|noise| noise.seed),
+    };
+    let mut builder = TrajectoryBuilder::new(opts.width, opts.height)
+        .max_slope(opts.max_slope)
+        .checkpoint_interval(opts.checkpoint_interval)
+        .paths(opts.paths)
+        .start(opts.start.unwrap_or_default())
+        .target(opts.target.unwrap_or_default())
+        .noise(opts.noise)
+        .cache_costs(opts.cache_costs)
+        .record_costs(opts.cost_image.is_some())
+        .debug(opts.debug)
+        .progress(true);
+    for gate in opts.waypoints {
+        builder = builder.gate(gate);
+    }
+    if let Some(path) = opts.obstacles {
+        builder = builder.obstacles(ObstacleMask::load(&path)?);
+    }
+    if let Some(path) = opts.heightmap {
+        builder = builder.heightmap(Raster::load(&path)?);
+    }
+    let options = builder.options()?;
+    let trajectories = if opts.simulation_type == "all" {
+        let verification = verify(&options);
+        print!("{}", verification);
+        verification.into_result()?
+    } else {
+        simulate(&opts.simulation_type, &options)?
+    };
+    if opts.paths > 1 {
+        print_trajectories(&trajectories);
+    } else {
+        println!("{:?}", trajectories[0].rows);
+    }
+    if let Some(path) = &opts.image {
+        image::write_image(path, ImageLayer::Energy, &options, &trajectories)?;
+    }
+    if let Some(path) = &opts.cost_image {
+        image::write_image(path, ImageLayer::Cost, &options, &trajectories)?;
+    }
+    if let Some(path) = &opts.svg_file {
+        svg::write_svg(path, &opts.svg, &options, &trajectories)?;
+    }
+    if let Some(path) = &opts.path_out {
+        path_export::write_path(path, &header, &options, &trajectories)?;
+    }
+
+    if let Some(sampler) = sampler {
+        sampler.stop();
+    }
+    AllocationData::dump_data(&mut File::create(opts.out_file)?, &metadata)?;
+    Ok(())
+}
 
-    SIMULATIONS.get(&opts.simulation_type.borrow()).unwrap()(
-        opts.out_file,
-        opts.width,
-        opts.height,
-        opts.debug,
-    )
+fn print_trajectories(trajectories: &[Trajectory]) {
+    for trajectory in trajectories {
+        println!("{:.6} {:?}", trajectory.cost.0, trajectory.rows);
+    }
 }
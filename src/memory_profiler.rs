@@ -1,55 +1,376 @@
 use std::{
     error::Error,
+    fmt::{self, Display, Formatter},
     io::Write,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
         RwLock,
     },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use itertools::Itertools;
-use jemalloc_ctl::{epoch, stats};
+use jemalloc_ctl::{arenas, epoch, raw, stats};
+
+use crate::allocator::{self, Backend};
+use crate::counting_allocator::{self, AllocationCounts};
 
 lazy_static! {
     static ref ALLOCATION_DATA: RwLock<Vec<AllocationData>> = RwLock::new(Vec::new());
+    /// Time the samples are taken relative to, set when the profiler is enabled.
+    static ref START: Instant = Instant::now();
 }
 
+/// Header line of the samples written by `dump_data`. Times are in seconds.
+pub const COLUMNS: &str = concat!(
+    "id\tphase\tcolumn\t",
+    "time\tuser_time\tsystem_time\t",
+    "allocated\tresident\tcorrection\tpeak_allocated\tpeak_resident\t",
+    "allocations\tdeallocations\treallocations\tbytes_allocated\tbytes_freed\t",
+    "active\tmapped\tmetadata\tretained\tfragmentation\tarena_fragmentation"
+);
+
 static ALLOCATION_DATA_ID: AtomicUsize = AtomicUsize::new(0);
-#[derive(Debug, Clone, Copy)]
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static EXTENDED_STATS: AtomicBool = AtomicBool::new(false);
+/// Highest values seen by the `Sampler` since the last explicit sample.
+static PEAK_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK_RESIDENT: AtomicUsize = AtomicUsize::new(0);
+
+/// Part of a strategy a sample was taken in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Before the simulation space is allocated.
+    Setup,
+    /// After a column was simulated.
+    Step,
+    /// While or after the trajectories are traced back.
+    Backtrack,
+    /// After the simulation space was dropped.
+    Teardown,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Setup => "setup",
+            Phase::Step => "step",
+            Phase::Backtrack => "backtrack",
+            Phase::Teardown => "teardown",
+        })
+    }
+}
+
+/// The run the samples belong to, written in front of them by `dump_data`.
+#[derive(Debug, Clone)]
+pub struct RunMetadata {
+    pub strategy: String,
+    pub width: usize,
+    pub height: usize,
+    /// Seed of the noise field, `None` if the energy came from elsewhere.
+    pub seed: Option<u32>,
+}
+
+/// jemalloc statistics beyond `allocated` and `resident`, see `AllocationData::enable_extended_stats`.
+#[derive(Debug, Clone)]
+pub struct ExtendedStats {
+    /// Bytes in pages backing allocations, `allocated` plus the free space within them.
+    pub active: usize,
+    /// Bytes in extents mapped by the allocator, a multiple of the chunk size.
+    pub mapped: usize,
+    /// Bytes the allocator uses for its own bookkeeping.
+    pub metadata: usize,
+    /// Bytes unmapped but kept as virtual memory for later use.
+    pub retained: usize,
+    /// `fragmentation` of every arena in use, by arena index.
+    pub arenas: Vec<(usize, f64)>,
+}
+
+impl ExtendedStats {
+    /// Reads the statistics of the current epoch.
+    fn read() -> jemalloc_ctl::Result<Self> {
+        let page: usize = unsafe { raw::read(b"arenas.page\0")? };
+        let mut arenas = Vec::new();
+        for arena in 0..arenas::narenas::read()? as usize {
+            let read = |stat: &str| -> jemalloc_ctl::Result<usize> {
+                let name = format!("stats.arenas.{}.{}\0", arena, stat);
+                unsafe { raw::read(name.as_bytes()) }
+            };
+            // Arenas no thread was ever assigned to have no statistics.
+            let active = match read("pactive") {
+                Ok(pages) if pages > 0 => pages * page,
+                _ => continue,
+            };
+            let allocated = read("small.allocated")? + read("large.allocated")?;
+            arenas.push((arena, fragmentation(allocated, active)));
+        }
+        Ok(ExtendedStats {
+            active: stats::active::read()?,
+            mapped: stats::mapped::read()?,
+            metadata: stats::metadata::read()?,
+            retained: stats::retained::read()?,
+            arenas,
+        })
+    }
+}
+
+/// Part of the active pages not taken by allocations, written as `fragmentation` for the whole
+/// process and as `arena:fragmentation` pairs for every arena.
+fn fragmentation(allocated: usize, active: usize) -> f64 {
+    if active == 0 {
+        0.0
+    } else {
+        1.0 - allocated as f64 / active as f64
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct AllocationData {
     pub id: usize,
+    pub phase: Phase,
+    /// Column the strategy had just finished, if the sample belongs to one.
+    pub column: Option<usize>,
+    /// Monotonic time since the profiler was enabled.
+    pub time: Duration,
+    /// CPU time spent by all threads of the process in user and kernel mode.
+    pub user_time: Duration,
+    pub system_time: Duration,
     pub allocated: usize,
     pub resident: usize,
     pub correction: usize,
+    /// Highest `allocated` and `resident` since the previous sample, including this one.
+    /// Only differs from them while a `Sampler` is running.
+    pub peak_allocated: usize,
+    pub peak_resident: usize,
+    /// Calls to the global allocator so far, zero unless the `CountingAllocator` is installed.
+    pub counts: AllocationCounts,
+    /// Only captured once enabled, as reading the arenas takes longer than the other statistics.
+    pub extended: Option<ExtendedStats>,
+}
+
+/// Polls the memory use on a thread of its own and keeps their peaks, so that
+/// spikes within a column show up in the next explicit sample. Stops when dropped.
+pub struct Sampler {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Sampler {
+    pub fn start(interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Some((allocated, resident)) = memory_usage() {
+                    PEAK_ALLOCATED.fetch_max(allocated, Ordering::Relaxed);
+                    PEAK_RESIDENT.fetch_max(resident, Ordering::Relaxed);
+                }
+            }
+        });
+        Sampler {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Waits for the thread to finish, so that it no longer touches the statistics.
+    pub fn stop(mut self) {
+        self.join();
+    }
+
+    fn join(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+/// jemalloc only knows about the memory it manages itself, so it is only asked if it is the
+/// global allocator, or if the program chose the global allocator without telling us.
+fn uses_jemalloc() -> bool {
+    matches!(allocator::active(), None | Some(Backend::Jemalloc))
+}
+
+/// Allocated and resident bytes. Without jemalloc, allocated are the bytes of the live blocks
+/// seen by the `CountingAllocator` and resident comes from `/proc/self/statm`.
+fn memory_usage() -> Option<(usize, usize)> {
+    if uses_jemalloc() {
+        epoch::advance().ok()?;
+        return Some((
+            stats::allocated::read().ok()?,
+            stats::resident::read().ok()?,
+        ));
+    }
+    let counts = counting_allocator::counts();
+    // The counters are read one after the other, so blocks allocated and freed by other threads
+    // in between may count as freed only.
+    Some((
+        counts.bytes_allocated.saturating_sub(counts.bytes_freed),
+        statm_resident()?,
+    ))
+}
+
+/// Reads `/proc/self/statm` into a buffer on the stack, so that polling it does not allocate
+/// and, with the bump allocator, leak.
+fn statm_resident() -> Option<usize> {
+    let mut buffer = [0u8; 128];
+    // SAFETY: the path is nul terminated and the buffer outlives the read.
+    let read = unsafe {
+        let fd = libc::open(
+            b"/proc/self/statm\0".as_ptr() as *const libc::c_char,
+            libc::O_RDONLY,
+        );
+        if fd < 0 {
+            return None;
+        }
+        let read = libc::read(fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len());
+        libc::close(fd);
+        read
+    };
+    if read <= 0 {
+        return None;
+    }
+    // The fields are size, resident, shared, text, lib, data and dirty, in pages.
+    let fields = std::str::from_utf8(&buffer[..read as usize]).ok()?;
+    let pages: usize = fields.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size as usize)
 }
+
+/// CPU time of the whole process in user and system mode.
+fn cpu_times() -> (Duration, Duration) {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: `getrusage` fills the struct it is given and RUSAGE_SELF is always valid.
+    let usage = unsafe {
+        libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr());
+        usage.assume_init()
+    };
+    let duration =
+        |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+    (duration(usage.ru_utime), duration(usage.ru_stime))
+}
+
 impl AllocationData {
-    fn get_data() -> Self {
-        epoch::advance().unwrap();
-
-        let allocated = stats::allocated::read().unwrap();
-        let resident = stats::resident::read().unwrap();
-        let correction =
-            ALLOCATION_DATA.read().unwrap().capacity() * std::mem::size_of::<AllocationData>();
-        AllocationData {
+    fn get_data(phase: Phase, column: Option<usize>) -> Result<Self, Box<dyn Error>> {
+        let time = START.elapsed();
+        let counts = counting_allocator::counts();
+        let (user_time, system_time) = cpu_times();
+        let (allocated, resident) = memory_usage().ok_or("could not read the memory usage")?;
+        let correction = ALLOCATION_DATA.read()?.capacity() * std::mem::size_of::<AllocationData>();
+        let peak_allocated = PEAK_ALLOCATED.swap(0, Ordering::Relaxed).max(allocated);
+        let peak_resident = PEAK_RESIDENT.swap(0, Ordering::Relaxed).max(resident);
+        let extended = if EXTENDED_STATS.load(Ordering::Relaxed) && uses_jemalloc() {
+            Some(ExtendedStats::read().map_err(|error| error.to_string())?)
+        } else {
+            None
+        };
+        Ok(AllocationData {
             id: ALLOCATION_DATA_ID.fetch_add(1, Ordering::Relaxed),
+            phase,
+            column,
+            time,
+            user_time,
+            system_time,
             allocated,
             resident,
             correction,
-        }
+            peak_allocated,
+            peak_resident,
+            counts,
+            extended,
+        })
     }
-    pub fn collect_data() -> Result<(), Box<dyn Error>> {
-        let data = AllocationData::get_data();
+    /// Samples are only collected once enabled, so that embedding the library does not accumulate them.
+    pub fn enable() {
+        lazy_static::initialize(&START);
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+    /// Adds `ExtendedStats` to the samples collected from now on, as long as jemalloc is in use.
+    pub fn enable_extended_stats() {
+        EXTENDED_STATS.store(true, Ordering::Relaxed);
+    }
+    pub fn collect_data(phase: Phase, column: Option<usize>) -> Result<(), Box<dyn Error>> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let data = AllocationData::get_data(phase, column)?;
         ALLOCATION_DATA.write()?.push(data);
         Ok(())
     }
-    pub fn dump_data<F: Write>(file: &mut F) -> Result<(), Box<dyn Error>> {
-        writeln!(file, "id\tallocated\tresident\tcorrection")?;
+    /// Writes the run metadata as a block of `# key: value` lines, followed by the samples.
+    pub fn dump_data<F: Write>(file: &mut F, metadata: &RunMetadata) -> Result<(), Box<dyn Error>> {
+        writeln!(file, "# strategy: {}", metadata.strategy)?;
+        writeln!(file, "# width: {}", metadata.width)?;
+        writeln!(file, "# height: {}", metadata.height)?;
+        match metadata.seed {
+            Some(seed) => writeln!(file, "# seed: {}", seed)?,
+            None => writeln!(file, "# seed:")?,
+        }
+        writeln!(file, "# threads: {}", rayon::current_num_threads())?;
+        match allocator::active() {
+            Some(backend) => writeln!(file, "# allocator: {}", backend)?,
+            None => writeln!(file, "# allocator:")?,
+        }
+        writeln!(
+            file,
+            "# memory_source: {}",
+            if uses_jemalloc() { "jemalloc" } else { "statm" }
+        )?;
+        writeln!(
+            file,
+            "# git_revision: {}",
+            option_env!("GIT_REVISION").unwrap_or("unknown")
+        )?;
+        for (limit, count) in counting_allocator::size_classes() {
+            writeln!(file, "# size_class.{}: {}", limit, count)?;
+        }
+        writeln!(file, "{}", COLUMNS)?;
         for data in ALLOCATION_DATA.read()?.iter().sorted_by_key(|&a| a.id) {
-            writeln!(
+            write!(
                 file,
-                "{}\t{}\t{}\t{}",
-                data.id, data.allocated, data.resident, data.correction
+                "{}\t{}\t{}\t{:.6}\t{:.6}\t{:.6}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t",
+                data.id,
+                data.phase,
+                data.column.map_or_else(String::new, |x| x.to_string()),
+                data.time.as_secs_f64(),
+                data.user_time.as_secs_f64(),
+                data.system_time.as_secs_f64(),
+                data.allocated,
+                data.resident,
+                data.correction,
+                data.peak_allocated,
+                data.peak_resident,
+                data.counts.allocations,
+                data.counts.deallocations,
+                data.counts.reallocations,
+                data.counts.bytes_allocated,
+                data.counts.bytes_freed
             )?;
+            match &data.extended {
+                Some(extended) => writeln!(
+                    file,
+                    "{}\t{}\t{}\t{}\t{:.6}\t{}",
+                    extended.active,
+                    extended.mapped,
+                    extended.metadata,
+                    extended.retained,
+                    fragmentation(data.allocated, extended.active),
+                    extended
+                        .arenas
+                        .iter()
+                        .map(|(arena, fragmentation)| format!("{}:{:.6}", arena, fragmentation))
+                        .join(",")
+                )?,
+                None => writeln!(file, "\t\t\t\t\t")?,
+            }
         }
         Ok(())
     }
@@ -1,30 +1,41 @@
-use std::{error::Error, fmt::Display, fs::File};
+use std::{error::Error, fmt::Display};
 
-use crate::memory_profiler::AllocationData;
+use crate::constraints::{check_feasible, Constraints, InfeasibleError};
+use crate::cost_field::CostField;
+use crate::memory_profiler::{AllocationData, Phase};
 use crate::score::Score;
-use crate::simulation::{LeftNode, RightNode};
+use crate::simulation::{
+    best_candidates, lowest_minimum, Candidate, CandidateStore, Candidates, KBest, KBestSimulation,
+    LeftNode, RankedNode, RightNode, SinglePath,
+};
+use crate::trajectory::Trajectory;
 use crate::{
     score,
-    simulation::{CostField, Simulation},
+    simulation::{Simulation, SimulationOptions},
 };
-use noise::{NoiseFn, Perlin};
-use std::io::Write;
 use std::sync::Arc;
 
 #[derive(Debug, Clone)]
-struct Node {
-    x: usize,
+struct Node<K: Candidates> {
     y: usize,
     parent: Option<usize>,
     aggregated_cost: Score,
     is_path: bool,
+    candidates: K::Store<Candidate<usize>>,
 }
-impl LeftNode for Node {
+impl<K: Candidates> LeftNode for Node<K> {
     fn aggregated_cost(&self) -> Score {
         self.aggregated_cost
     }
 }
-impl RightNode for Node {
+impl<K: Candidates> RankedNode for Node<K> {
+    type Candidate = Candidate<usize>;
+
+    fn candidates(&self) -> &[Candidate<usize>] {
+        self.candidates.as_slice()
+    }
+}
+impl<K: Candidates> RightNode for Node<K> {
     fn set_aggregated_cost(&mut self, score: Score) {
         self.aggregated_cost = score;
     }
@@ -33,17 +44,18 @@ impl RightNode for Node {
     }
 }
 
-struct SimulationSpace {
-    nodes: Vec<Node>,
+struct SimulationSpace<K: Candidates> {
+    nodes: Vec<Node<K>>,
     width: usize,
     height: usize,
-    noise: Arc<PerlinCostField>,
+    noise: Arc<dyn CostField>,
+    max_slope: Option<usize>,
+    constraints: Arc<Constraints>,
 }
 
-impl Simulation for SimulationSpace {
-    type LeftNodeType = Node;
-    type RightNodeType = Node;
-    type CostFieldType = PerlinCostField;
+impl<K: Candidates> Simulation for SimulationSpace<K> {
+    type LeftNodeType = Node<K>;
+    type RightNodeType = Node<K>;
 
     fn prepare_step_slices(
         &mut self,
@@ -64,27 +76,60 @@ impl Simulation for SimulationSpace {
         };
         (previous, next)
     }
-    fn get_cost_field(&self) -> Arc<Self::CostFieldType> {
+    fn get_cost_field(&self) -> Arc<dyn CostField> {
         self.noise.clone()
     }
 
+    fn max_slope(&self) -> Option<usize> {
+        self.max_slope
+    }
+
+    fn constraints(&self) -> Arc<Constraints> {
+        self.constraints.clone()
+    }
+
     fn set_parent_of(parent: &Self::LeftNodeType, child: &mut Self::RightNodeType) {
         child.parent = Some(parent.y);
     }
 }
 
-impl SimulationSpace {
-    fn new(width: usize, height: usize, noise_scale: f64) -> Self {
+impl<K: Candidates> KBestSimulation for SimulationSpace<K> {
+    fn add_candidate(
+        parent: &Self::LeftNodeType,
+        rank: usize,
+        cost: Score,
+        child: &mut Self::RightNodeType,
+    ) {
+        // The cheapest candidate comes first, it is the parent shown by `Display`.
+        if child.candidates.as_slice().is_empty() {
+            Self::set_parent_of(parent, child);
+        }
+        child.candidates.push(Candidate {
+            cost,
+            parent: parent.y,
+            rank,
+        });
+    }
+}
+
+impl<K: Candidates> SimulationSpace<K> {
+    fn new(
+        width: usize,
+        height: usize,
+        max_slope: Option<usize>,
+        constraints: Arc<Constraints>,
+        cost_field: Arc<dyn CostField>,
+    ) -> Self {
         let mut simulation_nodes = Vec::new();
         simulation_nodes.reserve((width * height) as usize);
-        for x in 0..width {
+        for _ in 0..width {
             for y in 0..height {
                 simulation_nodes.push(Node {
-                    x,
                     y,
                     parent: None,
                     aggregated_cost: Score::new(0.0),
                     is_path: false,
+                    candidates: Default::default(),
                 });
             }
         }
@@ -92,77 +137,112 @@ impl SimulationSpace {
             nodes: simulation_nodes,
             width,
             height,
-            noise: Arc::new(PerlinCostField {
-                width,
-                height,
-                perlin: Perlin::new(),
-                noise_scale,
-            }),
+            max_slope,
+            constraints,
+            noise: cost_field,
         }
     }
+
+    /// Backtracks the `k` cheapest trajectories and marks their nodes as a part of a path.
+    fn k_best_trajectories(&mut self, k: usize) -> Vec<(Score, Vec<usize>)> {
+        let last_column = (self.width - 1) * self.height..self.width * self.height;
+        best_candidates(&self.nodes[last_column], k, &self.constraints)
+            .into_iter()
+            .map(|(cost, mut y, mut rank)| {
+                let mut r_path = vec![y];
+                for x in (1..self.width).rev() {
+                    let node = &mut self.nodes[x * self.height + y];
+                    node.is_path = true;
+                    let candidate = &node.candidates.as_slice()[rank];
+                    y = candidate.parent;
+                    rank = candidate.rank;
+                    r_path.push(y);
+                }
+                self.nodes[y].is_path = true;
+                r_path.reverse();
+                (cost, r_path)
+            })
+            .collect()
+    }
 }
-struct PerlinCostField {
-    width: usize,
-    height: usize,
-    noise_scale: f64,
-    perlin: Perlin,
-}
-impl CostField for PerlinCostField {
-    type LeftNodeType = Node;
-    type RightNodeType = Node;
-    fn get_cost(&self, prev: &Self::LeftNodeType, curr: &Self::RightNodeType) -> Score {
-        let x = (curr.x + prev.x) as f64 / 2.0;
-        let y = (curr.y + prev.y) as f64 / 2.0;
-        let energy_needed = 1.05
-            + self.perlin.get([
-                x / self.width as f64 * self.noise_scale,
-                y / self.height as f64 * self.noise_scale,
-            ]);
-        let y_diff = curr.y as f64 - prev.y as f64;
-        let distance = (y_diff * y_diff + 1.0).sqrt();
-        Score::new(energy_needed * distance)
+
+pub fn naive(options: &SimulationOptions) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+    if options.paths > 1 {
+        run::<KBest>(options)
+    } else {
+        run::<SinglePath>(options)
     }
 }
 
-pub fn naive(out_path: String, x: usize, y: usize, debug: bool) -> Result<(), Box<dyn Error>> {
-    AllocationData::collect_data()?;
-    let mut simulation = SimulationSpace::new(x, y, 6.0);
+fn run<K: Candidates>(options: &SimulationOptions) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+    AllocationData::collect_data(Phase::Setup, None)?;
+    let mut simulation = SimulationSpace::<K>::new(
+        options.width,
+        options.height,
+        options.max_slope,
+        options.constraints.clone(),
+        options.cost_field.clone(),
+    );
     for x in 0..simulation.width {
-        print!("{} ", x);
-        std::io::stdout().flush().unwrap();
-        simulation.simulate_par(x);
-        AllocationData::collect_data()?;
-    }
-    println!("Done");
-    let last_column =
-        (simulation.width - 1) * simulation.height..simulation.width * simulation.height;
-    let mut target = simulation.nodes[last_column]
-        .iter_mut()
-        .min_by_key(|x| x.aggregated_cost)
+        options.report_column(x);
+        if options.paths > 1 {
+            simulation.simulate_par_k_best(x, options.paths);
+        } else {
+            simulation.simulate_par(x);
+        }
+        options.record_costs(x, || {
+            simulation.nodes[x * simulation.height..(x + 1) * simulation.height]
+                .iter()
+                .map(|node| node.aggregated_cost)
+        });
+        AllocationData::collect_data(Phase::Step, Some(x))?;
+    }
+    options.report_done();
+    let trajectories = if options.paths > 1 {
+        simulation.k_best_trajectories(options.paths)
+    } else {
+        let last_column =
+            (simulation.width - 1) * simulation.height..simulation.width * simulation.height;
+        let (_, target) = lowest_minimum(
+            simulation.nodes[last_column]
+                .iter()
+                .map(|x| (options.constraints.final_cost(x.y, x.aggregated_cost), x)),
+        )
         .unwrap();
-    let mut r_path = vec![];
-
-    for x in 1..=simulation.width {
-        if let Some(parent_id) = target.parent {
-            target = &mut simulation.nodes[(simulation.width - x) * simulation.height + parent_id];
-            target.is_path = true;
-            r_path.push(target.y);
+        let cost = check_feasible(
+            options
+                .constraints
+                .final_cost(target.y, target.aggregated_cost),
+        )?;
+        let mut y = target.y;
+        let mut r_path = vec![y];
+        for x in (1..simulation.width).rev() {
+            let node = &mut simulation.nodes[x * simulation.height + y];
+            node.is_path = true;
+            y = node.parent.unwrap();
+            r_path.push(y);
         }
+        simulation.nodes[y].is_path = true;
+        r_path.reverse();
+        vec![(cost, r_path)]
+    };
+    if trajectories.is_empty() {
+        return Err(InfeasibleError.into());
     }
-    if debug {
+    AllocationData::collect_data(Phase::Backtrack, None)?;
+    if options.debug {
         println!("{}", simulation);
     }
     drop(simulation);
-    r_path.reverse();
-    let path = r_path;
 
-    println!("{:?}", path);
-    AllocationData::collect_data()?;
-    AllocationData::dump_data(&mut File::create(out_path)?)?;
-    Ok(())
+    AllocationData::collect_data(Phase::Teardown, None)?;
+    Ok(trajectories
+        .into_iter()
+        .map(|(cost, rows)| Trajectory::new(cost, rows, &*options.cost_field))
+        .collect())
 }
 
-impl Display for SimulationSpace {
+impl<K: Candidates> Display for SimulationSpace<K> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for y in 0..self.height {
             for x in 0..self.width {
@@ -192,12 +272,7 @@ impl Display for SimulationSpace {
         for y in 0..self.height {
             for x in 1..self.width {
                 if let Some(parent_id) = self.nodes[x * self.height + y].parent {
-                    let cost = cost_f
-                        .get_cost(
-                            &self.nodes[x * self.height + y],
-                            &self.nodes[(x - 1) * self.height + parent_id],
-                        )
-                        .0;
+                    let cost = cost_f.get_cost(x - 1, parent_id, x, y).0;
                     min_cost = min_cost.min(cost);
                     max_cost = max_cost.max(cost);
                 }
@@ -209,12 +284,7 @@ impl Display for SimulationSpace {
             write!(f, " x ")?;
             for x in 1..self.width {
                 if let Some(parent_id) = self.nodes[x * self.height + y].parent {
-                    let cost = cost_f
-                        .get_cost(
-                            &self.nodes[x * self.height + y],
-                            &self.nodes[(x - 1) * self.height + parent_id],
-                        )
-                        .0;
+                    let cost = cost_f.get_cost(x - 1, parent_id, x, y).0;
                     let is_path = if self.nodes[x * self.height + y].is_path {
                         "\x1b[38;2;0;255;0m"
                     } else {
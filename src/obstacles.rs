@@ -0,0 +1,163 @@
+use std::{error::Error, fs, str::FromStr};
+
+use crate::raster;
+
+/// Cells the trajectory may neither enter nor cross.
+/// The mask is stretched over the simulation grid with nearest neighbour sampling.
+#[derive(Debug, Clone)]
+pub struct ObstacleMask {
+    width: usize,
+    height: usize,
+    /// Row-major, `true` for blocked cells.
+    blocked: Vec<bool>,
+    grid_width: usize,
+    grid_height: usize,
+}
+
+impl ObstacleMask {
+    /// Reads a PBM/PGM image, where dark pixels are blocked, or a text grid,
+    /// where `#`, `X` and `1` are blocked and every other character is free.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+        if raster::is_netpbm(&bytes) {
+            let image = raster::read_netpbm(&bytes)?;
+            let blocked = image
+                .samples
+                .iter()
+                .map(|&sample| (sample as u32) * 2 < image.max_value as u32)
+                .collect();
+            Self::new(image.width, image.height, blocked)
+        } else {
+            String::from_utf8(bytes)?.parse()
+        }
+    }
+
+    fn new(width: usize, height: usize, blocked: Vec<bool>) -> Result<Self, Box<dyn Error>> {
+        if width == 0 || height == 0 {
+            return Err("the obstacle mask is empty".into());
+        }
+        Ok(ObstacleMask {
+            width,
+            height,
+            blocked,
+            grid_width: width,
+            grid_height: height,
+        })
+    }
+
+    /// Stretches the mask over a simulation of the given size.
+    pub fn fit(&mut self, grid_width: usize, grid_height: usize) {
+        self.grid_width = grid_width;
+        self.grid_height = grid_height;
+    }
+
+    pub fn is_blocked(&self, x: usize, y: usize) -> bool {
+        let mask_x = x * self.width / self.grid_width;
+        let mask_y = y * self.height / self.grid_height;
+        self.blocked[mask_y * self.width + mask_x]
+    }
+
+    /// For every row of column `x`, the first blocked row at or below it, `grid_height` if there is none.
+    fn next_blocked(&self, x: usize) -> Vec<usize> {
+        let mut next = vec![self.grid_height; self.grid_height + 1];
+        for y in (0..self.grid_height).rev() {
+            next[y] = if self.is_blocked(x, y) {
+                y
+            } else {
+                next[y + 1]
+            };
+        }
+        next
+    }
+
+    /// Obstacles of the two neighbouring columns joined by one simulation step.
+    pub fn step(&self, previous_column: usize, current_column: usize) -> StepObstacles {
+        StepObstacles {
+            previous: self.next_blocked(previous_column),
+            current: self.next_blocked(current_column),
+        }
+    }
+}
+
+impl FromStr for ObstacleMask {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows: Vec<&str> = s.lines().collect();
+        let width = rows
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut blocked = vec![false; width * rows.len()];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                blocked[y * width + x] = matches!(cell, '#' | 'X' | '1');
+            }
+        }
+        Self::new(width, rows.len(), blocked)
+    }
+}
+
+/// Answers in constant time whether an edge between two neighbouring columns is blocked.
+pub struct StepObstacles {
+    previous: Vec<usize>,
+    current: Vec<usize>,
+}
+
+impl StepObstacles {
+    /// Whether the straight edge from row `y0` of the previous column to row `y1` of the current one
+    /// touches a blocked cell. The edge leaves the previous column halfway between the two rows,
+    /// so each column contributes the cells between its own endpoint and that midpoint.
+    pub fn blocks(&self, y0: usize, y1: usize) -> bool {
+        let (low, high) = if y0 <= y1 {
+            ((&self.previous, y0), (&self.current, y1))
+        } else {
+            ((&self.current, y1), (&self.previous, y0))
+        };
+        let sum = y0 + y1;
+        Self::any_blocked(low.0, low.1, sum / 2)
+            || Self::any_blocked(high.0, sum.div_ceil(2), high.1)
+    }
+
+    fn any_blocked(next_blocked: &[usize], first: usize, last: usize) -> bool {
+        next_blocked[first] <= last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_grid_blocks_hash_x_and_one() {
+        let mask: ObstacleMask = "#.X\n1o \n".parse().unwrap();
+        let blocked: Vec<_> = (0..2)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .map(|(x, y)| mask.is_blocked(x, y))
+            .collect();
+        assert_eq!(blocked, [true, false, true, true, false, false]);
+    }
+
+    #[test]
+    fn short_rows_of_a_text_grid_are_free() {
+        let mask: ObstacleMask = "#\n..1\n".parse().unwrap();
+        assert!(mask.is_blocked(0, 0));
+        assert!(!mask.is_blocked(1, 0) && !mask.is_blocked(2, 0));
+        assert!(mask.is_blocked(2, 1));
+    }
+
+    #[test]
+    fn empty_text_grid_is_rejected() {
+        assert!("".parse::<ObstacleMask>().is_err());
+        assert!("\n\n".parse::<ObstacleMask>().is_err());
+    }
+
+    #[test]
+    fn fitted_mask_is_stretched_over_the_grid() {
+        let mut mask: ObstacleMask = ".#\n".parse().unwrap();
+        mask.fit(4, 2);
+        let blocked: Vec<_> = (0..4).map(|x| mask.is_blocked(x, 1)).collect();
+        assert_eq!(blocked, [false, false, true, true]);
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::cost_field::{edge_length, NoiseOptions};
+use crate::simulation::SimulationOptions;
+use crate::trajectory::Trajectory;
+
+/// What the trajectories were computed from, written in front of them.
+#[derive(Debug, Clone, Serialize)]
+pub struct PathHeader {
+    pub strategy: String,
+    pub width: usize,
+    pub height: usize,
+    pub max_slope: Option<usize>,
+    /// Parameters of the noise field, `None` if the energy came from elsewhere.
+    pub noise: Option<NoiseOptions>,
+    pub heightmap: Option<String>,
+}
+
+/// One column of a trajectory. The edge is the one entering the column, the first column
+/// has none and reports the energy of its cell with a zero distance and edge cost.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Step {
+    pub x: usize,
+    pub y: usize,
+    /// Energy at the midpoint of the edge, the edge cost divided by its distance.
+    pub energy: f64,
+    pub distance: f64,
+    pub edge_cost: f64,
+    /// Start cost and the edge costs up to this column, the total cost adds the target cost.
+    pub cumulative_cost: f64,
+}
+
+#[derive(Serialize)]
+struct TrajectoryRecord {
+    total_cost: f64,
+    steps: Vec<Step>,
+}
+
+#[derive(Serialize)]
+struct PathFile<'a> {
+    #[serde(flatten)]
+    header: &'a PathHeader,
+    trajectories: Vec<TrajectoryRecord>,
+}
+
+/// Breaks the trajectory down into the steps whose costs add up to its total cost.
+pub fn steps(trajectory: &Trajectory, options: &SimulationOptions) -> Vec<Step> {
+    let rows = &trajectory.rows;
+    let mut cumulative_cost = options.constraints.start.cost(rows[0]).0;
+    let mut steps = vec![Step {
+        x: 0,
+        y: rows[0],
+        energy: options.cost_field.cell_energy(0, rows[0]),
+        distance: 0.0,
+        edge_cost: 0.0,
+        cumulative_cost,
+    }];
+    for (x, edge_cost) in trajectory
+        .step_costs
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i + 1, c.0))
+    {
+        let distance = edge_length(rows[x - 1], rows[x]);
+        cumulative_cost += edge_cost;
+        steps.push(Step {
+            x,
+            y: rows[x],
+            energy: edge_cost / distance,
+            distance,
+            edge_cost,
+            cumulative_cost,
+        });
+    }
+    steps
+}
+
+/// Writes the header and the steps of every trajectory as CSV if the path ends with `.csv`
+/// and as JSON otherwise. The CSV header is a block of `# key: value` comment lines.
+pub fn write_path(
+    path: &str,
+    header: &PathHeader,
+    options: &SimulationOptions,
+    trajectories: &[Trajectory],
+) -> Result<(), Box<dyn Error>> {
+    let file = PathFile {
+        header,
+        trajectories: trajectories
+            .iter()
+            .map(|trajectory| TrajectoryRecord {
+                total_cost: trajectory.cost.0,
+                steps: steps(trajectory, options),
+            })
+            .collect(),
+    };
+    let mut out = BufWriter::new(File::create(path)?);
+    if path.ends_with(".csv") {
+        write_csv(&mut out, &file)?;
+    } else {
+        serde_json::to_writer_pretty(&mut out, &file)?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_csv(out: &mut impl Write, file: &PathFile) -> Result<(), Box<dyn Error>> {
+    write_comments(out, "", &serde_json::to_value(file.header)?)?;
+    for (i, trajectory) in file.trajectories.iter().enumerate() {
+        writeln!(
+            out,
+            "# trajectories.{}.total_cost: {}",
+            i, trajectory.total_cost
+        )?;
+    }
+    writeln!(
+        out,
+        "trajectory,x,y,energy,distance,edge_cost,cumulative_cost"
+    )?;
+    for (i, trajectory) in file.trajectories.iter().enumerate() {
+        for step in &trajectory.steps {
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                i, step.x, step.y, step.energy, step.distance, step.edge_cost, step.cumulative_cost
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Nested objects become dotted keys.
+fn write_comments(out: &mut impl Write, prefix: &str, value: &Value) -> Result<(), Box<dyn Error>> {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                write_comments(out, &format!("{}{}.", prefix, key), value)?;
+            }
+        }
+        Value::Null => writeln!(out, "# {}:", prefix.trim_end_matches('.'))?,
+        Value::String(string) => writeln!(out, "# {}: {}", prefix.trim_end_matches('.'), string)?,
+        _ => writeln!(out, "# {}: {}", prefix.trim_end_matches('.'), value)?,
+    }
+    Ok(())
+}
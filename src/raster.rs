@@ -0,0 +1,351 @@
+use std::{error::Error, fs};
+
+/// Grayscale image, where higher samples are brighter.
+#[derive(Debug, Clone)]
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    pub max_value: u16,
+    /// Row-major samples in `0..=max_value`.
+    pub samples: Vec<u16>,
+}
+
+impl Raster {
+    /// Loads a PBM/PGM image or an 8 or 16 bit grayscale PNG.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+        if is_netpbm(&bytes) {
+            read_netpbm(&bytes)
+        } else if bytes.starts_with(b"\x89PNG") {
+            read_png(&bytes)
+        } else {
+            Err(format!("{} is neither a netpbm nor a PNG image", path).into())
+        }
+    }
+
+    /// Sample scaled to `0.0..=1.0`.
+    pub fn get_normalized(&self, x: usize, y: usize) -> f64 {
+        self.samples[y * self.width + x] as f64 / self.max_value as f64
+    }
+
+    /// Bilinear interpolation of normalized samples, where pixel `(x, y)` is centered at `(x + 0.5, y + 0.5)`.
+    /// Coordinates outside of the image take the value of the nearest edge.
+    pub fn sample_bilinear(&self, x: f64, y: f64) -> f64 {
+        let x = (x - 0.5).max(0.0).min((self.width - 1) as f64);
+        let y = (y - 0.5).max(0.0).min((self.height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (x - x0 as f64, y - y0 as f64);
+        let top = self.get_normalized(x0, y0) * (1.0 - tx) + self.get_normalized(x1, y0) * tx;
+        let bottom = self.get_normalized(x0, y1) * (1.0 - tx) + self.get_normalized(x1, y1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+/// Reads an 8 or 16 bit grayscale PNG, the alpha channel is ignored.
+pub fn read_png(bytes: &[u8]) -> Result<Raster, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new_with_limits(bytes, png::Limits { bytes: usize::MAX });
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info()?;
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        _ => return Err("only grayscale PNG images are supported".into()),
+    };
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    check_size(width, height)?;
+    let rows = data.chunks(info.line_size).take(height);
+    let (max_value, samples) = match info.bit_depth {
+        png::BitDepth::Eight => (
+            u8::MAX as u16,
+            rows.flat_map(|row| row.iter().step_by(channels).take(width))
+                .map(|&sample| sample as u16)
+                .collect(),
+        ),
+        png::BitDepth::Sixteen => (
+            u16::MAX,
+            rows.flat_map(|row| row.chunks(2).step_by(channels).take(width))
+                .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+                .collect(),
+        ),
+        _ => return Err("only 8 and 16 bit PNG images are supported".into()),
+    };
+    Ok(Raster {
+        width,
+        height,
+        max_value,
+        samples,
+    })
+}
+
+/// Images without any pixel have nothing to sample.
+fn check_size(width: usize, height: usize) -> Result<(), Box<dyn Error>> {
+    if width == 0 || height == 0 {
+        return Err(format!("invalid image size {}x{}", width, height).into());
+    }
+    Ok(())
+}
+
+fn check_sample(sample: usize, max_value: usize) -> Result<u16, Box<dyn Error>> {
+    if sample > max_value {
+        return Err(format!("sample {} exceeds the maximal value {}", sample, max_value).into());
+    }
+    Ok(sample as u16)
+}
+
+pub fn is_netpbm(bytes: &[u8]) -> bool {
+    matches!(bytes, [b'P', b'1'..=b'6', ..])
+}
+
+/// Reads a PBM (`P1`, `P4`) or PGM (`P2`, `P5`) image. Black PBM pixels become 0, white ones 1.
+pub fn read_netpbm(bytes: &[u8]) -> Result<Raster, Box<dyn Error>> {
+    let mut header = Header { bytes, position: 2 };
+    let magic = bytes.get(..2).ok_or("file is too short")?;
+    let width = header.number()?;
+    let height = header.number()?;
+    check_size(width, height)?;
+    let max_value = match magic {
+        b"P1" | b"P4" => 1,
+        b"P2" | b"P5" => header.number()?,
+        _ => return Err("only P1, P2, P4 and P5 netpbm images are supported".into()),
+    };
+    if max_value == 0 || max_value > u16::MAX as usize {
+        return Err(format!("invalid maximal sample value {}", max_value).into());
+    }
+    let count = width * height;
+    let samples = match magic {
+        b"P1" => (0..count)
+            .map(|_| header.bit().map(|bit| 1 - bit))
+            .collect::<Result<_, _>>()?,
+        b"P2" => (0..count)
+            .map(|_| {
+                header
+                    .number()
+                    .and_then(|sample| check_sample(sample, max_value))
+            })
+            .collect::<Result<_, _>>()?,
+        b"P4" => {
+            let data = header.data()?;
+            let row_bytes = width.div_ceil(8);
+            if data.len() < row_bytes * height {
+                return Err("image data is truncated".into());
+            }
+            (0..count)
+                .map(|i| {
+                    let (y, x) = (i / width, i % width);
+                    let byte = data[y * row_bytes + x / 8];
+                    1 - ((byte >> (7 - x % 8)) & 1) as u16
+                })
+                .collect()
+        }
+        _ => {
+            let data = header.data()?;
+            if max_value < 256 {
+                if data.len() < count {
+                    return Err("image data is truncated".into());
+                }
+                data[..count]
+                    .iter()
+                    .map(|&sample| check_sample(sample as usize, max_value))
+                    .collect::<Result<_, _>>()?
+            } else {
+                if data.len() < 2 * count {
+                    return Err("image data is truncated".into());
+                }
+                data[..2 * count]
+                    .chunks(2)
+                    .map(|sample| {
+                        let sample = u16::from_be_bytes([sample[0], sample[1]]);
+                        check_sample(sample as usize, max_value)
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+        }
+    };
+    Ok(Raster {
+        width,
+        height,
+        max_value: max_value as u16,
+        samples,
+    })
+}
+
+struct Header<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Header<'a> {
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(&byte) = self.bytes.get(self.position) {
+            if byte == b'#' {
+                while self.bytes.get(self.position).is_some_and(|&b| b != b'\n') {
+                    self.position += 1;
+                }
+            } else if byte.is_ascii_whitespace() {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, Box<dyn Error>> {
+        self.skip_whitespace_and_comments();
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(u8::is_ascii_digit)
+        {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err("expected a number in the netpbm file".into());
+        }
+        Ok(std::str::from_utf8(&self.bytes[start..self.position])?.parse()?)
+    }
+
+    /// Plain PBM pixels do not need to be separated by whitespace.
+    fn bit(&mut self) -> Result<u16, Box<dyn Error>> {
+        self.skip_whitespace_and_comments();
+        let bit = match self.bytes.get(self.position) {
+            Some(b'0') => 0,
+            Some(b'1') => 1,
+            _ => return Err("expected 0 or 1 in the PBM file".into()),
+        };
+        self.position += 1;
+        Ok(bit)
+    }
+
+    /// Binary data starts after exactly one whitespace character following the header.
+    fn data(&mut self) -> Result<&'a [u8], Box<dyn Error>> {
+        self.bytes
+            .get(self.position + 1..)
+            .ok_or_else(|| "image data is missing".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(
+        width: u32,
+        height: u32,
+        color: png::ColorType,
+        depth: png::BitDepth,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(data)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn plain_pbm_maps_black_to_zero() {
+        let image = read_netpbm(b"P1\n# comment\n3 2\n1 0 1\n001\n").unwrap();
+        assert_eq!((image.width, image.height, image.max_value), (3, 2, 1));
+        assert_eq!(image.samples, [0, 1, 0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn raw_pbm_pads_rows_to_whole_bytes() {
+        let image = read_netpbm(b"P4\n3 2\n\xa0\x20").unwrap();
+        assert_eq!((image.width, image.height, image.max_value), (3, 2, 1));
+        assert_eq!(image.samples, [0, 1, 0, 1, 1, 0]);
+        assert!(read_netpbm(b"P4\n3 2\n\xa0").is_err());
+    }
+
+    #[test]
+    fn plain_pgm_keeps_the_samples() {
+        let image = read_netpbm(b"P2 2 2 255 0 128\n255 7").unwrap();
+        assert_eq!((image.width, image.height, image.max_value), (2, 2, 255));
+        assert_eq!(image.samples, [0, 128, 255, 7]);
+    }
+
+    #[test]
+    fn raw_pgm_reads_one_or_two_bytes_per_sample() {
+        let image = read_netpbm(b"P5\n2 1\n255\n\x00\xff").unwrap();
+        assert_eq!((image.max_value, image.samples), (255, vec![0, 255]));
+        let image = read_netpbm(b"P5\n2 1\n65535\n\x01\x02\xff\xff").unwrap();
+        assert_eq!((image.max_value, image.samples), (65535, vec![258, 65535]));
+        assert!(read_netpbm(b"P5\n2 1\n65535\n\x01\x02\xff").is_err());
+    }
+
+    #[test]
+    fn netpbm_rejects_colour_images_and_bad_headers() {
+        assert!(read_netpbm(b"P3\n1 1\n255\n0 0 0").is_err());
+        assert!(read_netpbm(b"P2\n1 1\n0\n0").is_err());
+        assert!(read_netpbm(b"P2\n1\n").is_err());
+        assert!(read_netpbm(b"P1\n2 1\n1 2").is_err());
+    }
+
+    #[test]
+    fn netpbm_rejects_empty_images() {
+        assert!(read_netpbm(b"P2\n0 0\n255\n").is_err());
+        assert!(read_netpbm(b"P5\n0 3\n255\n").is_err());
+        assert!(read_netpbm(b"P1\n2 0\n").is_err());
+    }
+
+    #[test]
+    fn pgm_rejects_samples_above_the_maximal_value() {
+        assert!(read_netpbm(b"P2\n2 1\n255\n0 256").is_err());
+        assert!(read_netpbm(b"P2\n1 1\n65535\n99999").is_err());
+        assert!(read_netpbm(b"P5\n1 1\n100\n\xff").is_err());
+        assert!(read_netpbm(b"P5\n1 1\n1000\n\xff\xff").is_err());
+    }
+
+    #[test]
+    fn png_reads_8_and_16_bit_grayscale() {
+        let bytes = png(
+            2,
+            2,
+            png::ColorType::Grayscale,
+            png::BitDepth::Eight,
+            &[0, 64, 128, 255],
+        );
+        let image = read_png(&bytes).unwrap();
+        assert_eq!((image.width, image.height, image.max_value), (2, 2, 255));
+        assert_eq!(image.samples, [0, 64, 128, 255]);
+
+        let bytes = png(
+            1,
+            1,
+            png::ColorType::Grayscale,
+            png::BitDepth::Sixteen,
+            &[1, 2],
+        );
+        let image = read_png(&bytes).unwrap();
+        assert_eq!((image.max_value, image.samples), (65535, vec![258]));
+    }
+
+    #[test]
+    fn png_ignores_the_alpha_channel() {
+        let bytes = png(
+            2,
+            1,
+            png::ColorType::GrayscaleAlpha,
+            png::BitDepth::Eight,
+            &[10, 255, 20, 0],
+        );
+        assert_eq!(read_png(&bytes).unwrap().samples, [10, 20]);
+    }
+
+    #[test]
+    fn png_rejects_colour_and_low_bit_depths() {
+        let bytes = png(1, 1, png::ColorType::RGB, png::BitDepth::Eight, &[1, 2, 3]);
+        assert!(read_png(&bytes).is_err());
+        let bytes = png(8, 1, png::ColorType::Grayscale, png::BitDepth::One, &[0xff]);
+        assert!(read_png(&bytes).is_err());
+    }
+}
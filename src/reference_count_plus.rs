@@ -1,43 +1,67 @@
 use std::{
     error::Error,
     fmt::{Display, Formatter},
-    fs::File,
-    io::Write,
 };
 
-use crate::memory_profiler::AllocationData;
+use crate::constraints::{check_feasible, Constraints, InfeasibleError};
+use crate::cost_field::CostField;
+use crate::memory_profiler::{AllocationData, Phase};
 use crate::score::Score;
-use crate::simulation::{CostField, Simulation};
-use crate::simulation::{LeftNode, RightNode};
+use crate::simulation::{
+    best_candidates, lowest_minimum, CandidateStore, Candidates, KBest, KBestSimulation, LeftNode,
+    RankedNode, RightNode, SinglePath,
+};
+use crate::simulation::{Simulation, SimulationOptions};
+use crate::trajectory::Trajectory;
+use crate::utils::fmt_ancestor_tree;
 
-use noise::{NoiseFn, Perlin};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 #[derive(Debug, Clone)]
 struct Parent {
     y: usize,
     parent: Option<Arc<Parent>>,
 }
-#[derive(Debug, Clone)]
-struct Node {
-    x: usize,
+/// Every candidate already links to the whole rest of its trajectory, so no rank is needed.
+type Link = (Score, Arc<Parent>);
+
+struct Node<K: Candidates> {
     y: usize,
     parent: Option<Arc<Parent>>,
     aggregated_cost: Score,
+    candidates: K::Store<Link>,
+}
+
+impl<K: Candidates> Clone for Node<K> {
+    fn clone(&self) -> Self {
+        Node {
+            y: self.y,
+            parent: self.parent.clone(),
+            aggregated_cost: self.aggregated_cost,
+            candidates: self.candidates.cloned(),
+        }
+    }
 }
 
-impl Default for Node {
+impl<K: Candidates> Default for Node<K> {
     fn default() -> Self {
-        Node::new(0, 0)
+        Node::new(0)
     }
 }
 
-impl LeftNode for Node {
+impl<K: Candidates> LeftNode for Node<K> {
     fn aggregated_cost(&self) -> Score {
         self.aggregated_cost
     }
 }
-impl RightNode for Node {
+impl<K: Candidates> RankedNode for Node<K> {
+    type Candidate = Link;
+
+    fn candidates(&self) -> &[Link] {
+        self.candidates.as_slice()
+    }
+}
+impl<K: Candidates> RightNode for Node<K> {
     fn set_aggregated_cost(&mut self, score: Score) {
         self.aggregated_cost = score;
     }
@@ -46,7 +70,7 @@ impl RightNode for Node {
     }
 }
 
-impl Node {
+impl<K: Candidates> Node<K> {
     fn reverse_path(self: Arc<Self>) -> ReversePath {
         ReversePath {
             value: Some(Arc::new(Parent {
@@ -55,12 +79,18 @@ impl Node {
             })),
         }
     }
-    const fn new(x: usize, y: usize) -> Self {
+    /// Links to the previous column this node still keeps alive.
+    fn links(&self) -> impl Iterator<Item = &Arc<Parent>> {
+        self.parent
+            .iter()
+            .chain(self.candidates.as_slice().iter().map(|(_, link)| link))
+    }
+    fn new(y: usize) -> Self {
         Node {
-            x,
             y,
             parent: None,
             aggregated_cost: Score::new(0.0),
+            candidates: Default::default(),
         }
     }
 }
@@ -78,34 +108,41 @@ impl Iterator for ReversePath {
     }
 }
 
-struct SimulationSpace {
+struct SimulationSpace<K: Candidates> {
     width: usize,
     height: usize,
-    cost_field: Arc<PerlinCostField>,
-    previous: Vec<Node>,
-    current: Vec<Node>,
-    x: usize,
+    cost_field: Arc<dyn CostField>,
+    max_slope: Option<usize>,
+    constraints: Arc<Constraints>,
+    /// Column of the frontier.
+    column: usize,
+    previous: Vec<Node<K>>,
+    current: Vec<Node<K>>,
 }
 
-impl Simulation for SimulationSpace {
-    type LeftNodeType = Node;
-    type RightNodeType = Node;
-    type CostFieldType = PerlinCostField;
+impl<K: Candidates> Simulation for SimulationSpace<K> {
+    type LeftNodeType = Node<K>;
+    type RightNodeType = Node<K>;
 
-    fn get_cost_field(&self) -> Arc<Self::CostFieldType> {
+    fn get_cost_field(&self) -> Arc<dyn CostField> {
         self.cost_field.clone()
     }
 
+    fn max_slope(&self) -> Option<usize> {
+        self.max_slope
+    }
+
+    fn constraints(&self) -> Arc<Constraints> {
+        self.constraints.clone()
+    }
+
     fn prepare_step_slices(
         &mut self,
-        _: usize,
+        iteration: usize,
     ) -> (&[Self::LeftNodeType], &mut [Self::RightNodeType]) {
+        self.column = iteration;
         std::mem::swap(&mut self.current, &mut self.previous);
-        self.x += 1;
-        self.current = (0..self.height)
-            .into_par_iter()
-            .map(|y| Node::new(self.x, y))
-            .collect();
+        self.current = (0..self.height).into_par_iter().map(Node::new).collect();
 
         (&self.previous[..], &mut self.current[..])
     }
@@ -118,86 +155,200 @@ impl Simulation for SimulationSpace {
     }
 }
 
-impl SimulationSpace {
-    fn new(width: usize, height: usize, noise_scale: f64) -> Self {
+impl<K: Candidates> KBestSimulation for SimulationSpace<K> {
+    fn add_candidate(
+        parent: &Self::LeftNodeType,
+        rank: usize,
+        cost: Score,
+        child: &mut Self::RightNodeType,
+    ) {
+        let link = Arc::new(Parent {
+            y: parent.y,
+            parent: parent
+                .candidates
+                .as_slice()
+                .get(rank)
+                .map(|(_, link)| link.clone()),
+        });
+        child.candidates.push((cost, link));
+    }
+}
+
+impl<K: Candidates> SimulationSpace<K> {
+    fn new(
+        width: usize,
+        height: usize,
+        max_slope: Option<usize>,
+        constraints: Arc<Constraints>,
+        cost_field: Arc<dyn CostField>,
+    ) -> Self {
         SimulationSpace {
             width,
             height,
-            cost_field: Arc::new(PerlinCostField {
-                width,
-                height,
-                perlin: Perlin::new(),
-                noise_scale,
-            }),
+            max_slope,
+            constraints,
+            cost_field,
+            column: 0,
             previous: Vec::new(),
             current: Vec::new(),
-            x: 0,
         }
     }
-}
-struct PerlinCostField {
-    width: usize,
-    height: usize,
-    noise_scale: f64,
-    perlin: Perlin,
-}
-impl CostField for PerlinCostField {
-    type LeftNodeType = Node;
-    type RightNodeType = Node;
-    fn get_cost(&self, prev: &Self::LeftNodeType, curr: &Self::RightNodeType) -> Score {
-        let x = (curr.x + prev.x) as f64 / 2.0;
-        let y = (curr.y + prev.y) as f64 / 2.0;
-        let energy_needed = 1.05
-            + self.perlin.get([
-                x / self.width as f64 * self.noise_scale,
-                y / self.height as f64 * self.noise_scale,
-            ]);
-        let y_diff = curr.y as f64 - prev.y as f64;
-        let distance = (y_diff * y_diff + 1.0).sqrt();
-        Score::new(energy_needed * distance)
+
+    /// Rows of the cheapest trajectory ending in the frontier, if there is one.
+    fn best_path(&self) -> Option<Vec<usize>> {
+        if self
+            .current
+            .iter()
+            .any(|node| !node.candidates.as_slice().is_empty())
+        {
+            return self.k_best_trajectories(1).pop().map(|(_, path)| path);
+        }
+        let (_, target) = lowest_minimum(self.current.iter().map(|node| {
+            (
+                self.constraints.final_cost(node.y, node.aggregated_cost),
+                node,
+            )
+        }))?;
+        if !self
+            .constraints
+            .final_cost(target.y, target.aggregated_cost)
+            .is_finite()
+        {
+            return None;
+        }
+        let mut path: Vec<_> = Arc::new(target.clone())
+            .reverse_path()
+            .map(|parent| parent.y)
+            .collect();
+        path.reverse();
+        Some(path)
+    }
+
+    /// `alive[x][y]` tells whether row `y` of column `x` is still referenced from the frontier.
+    /// Every child owns its own link to a parent, so a row may be referenced through several links.
+    fn surviving_ancestors(&self) -> Vec<Vec<bool>> {
+        let mut alive = vec![vec![false; self.height]; self.column];
+        let mut visited = HashSet::new();
+        let mut stack: Vec<_> = self
+            .current
+            .iter()
+            .flat_map(Node::links)
+            .map(|link| (self.column - 1, link))
+            .collect();
+        while let Some((x, link)) = stack.pop() {
+            if !visited.insert(Arc::as_ptr(link)) {
+                continue;
+            }
+            alive[x][link.y] = true;
+            stack.extend(link.parent.iter().map(|parent| (x.wrapping_sub(1), parent)));
+        }
+        alive
+    }
+
+    /// Follows the parent chains of the `k` cheapest trajectories ending in the current column.
+    fn k_best_trajectories(&self, k: usize) -> Vec<(Score, Vec<usize>)> {
+        best_candidates(&self.current, k, &self.constraints)
+            .into_iter()
+            .map(|(cost, y, rank)| {
+                let mut r_path = vec![y];
+                let chain = ReversePath {
+                    value: self.current[y]
+                        .candidates
+                        .as_slice()
+                        .get(rank)
+                        .map(|(_, link)| link.clone()),
+                };
+                r_path.extend(chain.map(|parent| parent.y));
+                r_path.reverse();
+                (cost, r_path)
+            })
+            .collect()
     }
 }
 
 pub fn reference_count_plus(
-    out_path: String,
-    x: usize,
-    y: usize,
-    _debug: bool,
-) -> Result<(), Box<dyn Error>> {
-    AllocationData::collect_data()?;
-    let mut simulation = SimulationSpace::new(x, y, 6.0);
+    options: &SimulationOptions,
+) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+    if options.paths > 1 {
+        run::<KBest>(options)
+    } else {
+        run::<SinglePath>(options)
+    }
+}
+
+fn run<K: Candidates>(options: &SimulationOptions) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+    AllocationData::collect_data(Phase::Setup, None)?;
+    let mut simulation = SimulationSpace::<K>::new(
+        options.width,
+        options.height,
+        options.max_slope,
+        options.constraints.clone(),
+        options.cost_field.clone(),
+    );
     for x in 0..simulation.width {
-        print!("{} ", x);
-        std::io::stdout().flush().unwrap();
-        simulation.simulate_par(x);
-        AllocationData::collect_data()?;
-    }
-    println!("Done");
-    let target = simulation
-        .current
-        .iter_mut()
-        .min_by_key(|x| LeftNode::aggregated_cost(*x))
-        .unwrap()
-        .clone();
-    drop(simulation);
+        options.report_column(x);
+        if options.paths > 1 {
+            simulation.simulate_par_k_best(x, options.paths);
+        } else {
+            simulation.simulate_par(x);
+        }
+        options.record_costs(x, || {
+            simulation.current.iter().map(|node| node.aggregated_cost)
+        });
+        AllocationData::collect_data(Phase::Step, Some(x))?;
+    }
+    options.report_done();
+    let trajectories = if options.paths > 1 {
+        simulation.k_best_trajectories(options.paths)
+    } else {
+        let (_, target) = lowest_minimum(simulation.current.iter().map(|x| {
+            (
+                options
+                    .constraints
+                    .final_cost(x.y, LeftNode::aggregated_cost(x)),
+                x,
+            )
+        }))
+        .unwrap();
+        let target = target.clone();
+        let cost = check_feasible(
+            options
+                .constraints
+                .final_cost(target.y, target.aggregated_cost),
+        )?;
 
-    let mut r_path = vec![];
+        let mut r_path = vec![];
 
-    for node in Arc::new(target).reverse_path() {
-        r_path.push(node.y);
+        for node in Arc::new(target).reverse_path() {
+            r_path.push(node.y);
+        }
+        r_path.reverse();
+        vec![(cost, r_path)]
+    };
+    if trajectories.is_empty() {
+        return Err(InfeasibleError.into());
     }
-    r_path.reverse();
-    let path = r_path;
-
-    println!("{:?}", path);
+    AllocationData::collect_data(Phase::Backtrack, None)?;
+    if options.debug {
+        println!("{}", simulation);
+    }
+    drop(simulation);
 
-    AllocationData::collect_data()?;
-    AllocationData::dump_data(&mut File::create(out_path)?)?;
-    Ok(())
+    AllocationData::collect_data(Phase::Teardown, None)?;
+    Ok(trajectories
+        .into_iter()
+        .map(|(cost, rows)| Trajectory::new(cost, rows, &*options.cost_field))
+        .collect())
 }
 
-impl Display for SimulationSpace {
-    fn fmt(&self, _: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        todo!()
+impl<K: Candidates> Display for SimulationSpace<K> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let frontier: Vec<_> = self
+            .current
+            .iter()
+            .map(|node| node.aggregated_cost)
+            .collect();
+        let path = self.best_path().unwrap_or_default();
+        fmt_ancestor_tree(f, &self.surviving_ancestors(), &frontier, &path)
     }
 }
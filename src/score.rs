@@ -23,6 +23,9 @@ impl NotNaNf64 {
             Some(NotNaNf64(f))
         }
     }
+    pub fn is_finite(&self) -> bool {
+        self.0.is_finite()
+    }
 }
 
 impl PartialEq for NotNaNf64 {
@@ -45,8 +48,12 @@ impl Ord for NotNaNf64 {
     }
 }
 
+/// An infinite cost marks an unreachable node, so it absorbs anything added to it.
 impl Add<NotNaNf64> for NotNaNf64 {
     fn add(self, other: NotNaNf64) -> <Self as std::ops::Add<NotNaNf64>>::Output {
+        if self == INFINITY || other == INFINITY {
+            return Some(INFINITY);
+        }
         NotNaNf64::new_checked(self.0 + other.0)
     }
     type Output = Option<NotNaNf64>;
@@ -1,10 +1,70 @@
-use crate::score::Score;
-use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
-use std::sync::Arc;
-pub trait CostField {
-    type LeftNodeType;
-    type RightNodeType;
-    fn get_cost(&self, a: &Self::LeftNodeType, b: &Self::RightNodeType) -> Score;
+use crate::constraints::Constraints;
+use crate::cost_field::CostField;
+use crate::image::ColumnCosts;
+use crate::obstacles::StepObstacles;
+use crate::score::{self, Score};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use std::{io::Write, ops::Range, sync::Arc};
+
+/// Parameters shared by every simulation strategy.
+#[derive(Clone)]
+pub struct SimulationOptions {
+    pub width: usize,
+    pub height: usize,
+    /// Maximal number of rows the trajectory may move between two neighbouring columns.
+    /// `None` lets every node of the previous column be a predecessor.
+    pub max_slope: Option<usize>,
+    /// Distance between the columns whose costs are kept by the `checkpoint` strategy.
+    /// `None` picks the square root of the width.
+    pub checkpoint_interval: Option<usize>,
+    /// Number of cheapest distinct trajectories to find.
+    pub paths: usize,
+    pub constraints: Arc<Constraints>,
+    pub cost_field: Arc<dyn CostField>,
+    /// Prints the simulation grid of strategies that keep it.
+    pub debug: bool,
+    /// Prints every finished column.
+    pub progress: bool,
+    /// Filled with the costs of every column by strategies that compute them in order.
+    pub column_costs: Option<Arc<ColumnCosts>>,
+}
+
+impl SimulationOptions {
+    pub fn report_column(&self, x: usize) {
+        if self.progress {
+            print!("{} ", x);
+            std::io::stdout().flush().unwrap();
+        }
+    }
+
+    /// Hands the aggregated costs of column `x` to `column_costs`, if set.
+    pub fn record_costs<I: IntoIterator<Item = Score>>(&self, x: usize, costs: impl FnOnce() -> I) {
+        if let Some(column_costs) = &self.column_costs {
+            column_costs.record(x, costs());
+        }
+    }
+
+    pub fn report_done(&self) {
+        if self.progress {
+            println!("Done");
+        }
+    }
+}
+
+/// Rows of the previous column that may precede row `y` of the current one.
+pub fn predecessor_window(len: usize, y: usize, max_slope: Option<usize>) -> Range<usize> {
+    match max_slope {
+        Some(k) => {
+            let end = len.min(y.saturating_add(k).saturating_add(1));
+            y.saturating_sub(k).min(end)..end
+        }
+        None => 0..len,
+    }
+}
+fn is_blocked(obstacles: &Option<StepObstacles>, y0: usize, y1: usize) -> bool {
+    obstacles
+        .as_ref()
+        .is_some_and(|obstacles| obstacles.blocks(y0, y1))
 }
 
 pub trait LeftNode {
@@ -14,38 +74,277 @@ pub trait RightNode {
     fn set_aggregated_cost(&mut self, score: Score);
     fn aggregated_cost(&self) -> Score;
 }
+
+/// One of the trajectories reaching a node: its cost, the predecessor
+/// and which of the predecessor's own trajectories it continues.
+#[derive(Debug, Clone)]
+pub struct Candidate<P> {
+    pub cost: Score,
+    pub parent: P,
+    pub rank: usize,
+}
+
+/// Anything that stands for one of the trajectories reaching a node.
+pub trait Ranked {
+    fn cost(&self) -> Score;
+}
+impl<P> Ranked for Candidate<P> {
+    fn cost(&self) -> Score {
+        self.cost
+    }
+}
+impl<P> Ranked for (Score, P) {
+    fn cost(&self) -> Score {
+        self.0
+    }
+}
+
+/// Where a node keeps its candidates. Strategies looking for the single cheapest trajectory
+/// store them in `NoCandidates`, so that their nodes are not any larger than without k-best mode.
+pub trait CandidateStore<T>: Default + Send + Sync {
+    fn as_slice(&self) -> &[T];
+    fn push(&mut self, candidate: T);
+    /// Stands in for `Clone`, which cannot be required of stores whose candidates link
+    /// to nodes holding the same store.
+    fn cloned(&self) -> Self
+    where
+        T: Clone;
+}
+impl<T: Send + Sync> CandidateStore<T> for Vec<T> {
+    fn as_slice(&self) -> &[T] {
+        self
+    }
+    fn push(&mut self, candidate: T) {
+        Vec::push(self, candidate);
+    }
+    fn cloned(&self) -> Self
+    where
+        T: Clone,
+    {
+        self.clone()
+    }
+}
+
+/// Keeps no candidates, the node only knows the cheapest trajectory reaching it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoCandidates;
+
+impl<T> CandidateStore<T> for NoCandidates {
+    fn as_slice(&self) -> &[T] {
+        &[]
+    }
+    fn push(&mut self, _: T) {
+        unreachable!("the node only keeps the cheapest trajectory")
+    }
+    fn cloned(&self) -> Self {
+        NoCandidates
+    }
+}
+
+/// Chooses the candidate store of the nodes of a strategy, whose candidates may link to nodes
+/// of the same type.
+pub trait Candidates: Send + Sync + 'static {
+    type Store<T: Send + Sync>: CandidateStore<T>;
+}
+
+/// Nodes keep up to `k` candidates for the `k` cheapest trajectories.
+pub struct KBest;
+impl Candidates for KBest {
+    type Store<T: Send + Sync> = Vec<T>;
+}
+
+/// Nodes only know the cheapest trajectory.
+pub struct SinglePath;
+impl Candidates for SinglePath {
+    type Store<T: Send + Sync> = NoCandidates;
+}
+
+/// A node that remembers the cheapest trajectories reaching it, cheapest first.
+/// A node without candidates only knows the cheapest one, which costs its aggregated cost.
+pub trait RankedNode: LeftNode {
+    type Candidate: Ranked;
+
+    fn candidates(&self) -> &[Self::Candidate];
+
+    fn candidate_count(&self) -> usize {
+        self.candidates().len().max(1)
+    }
+    fn candidate_cost(&self, rank: usize) -> Score {
+        self.candidates()
+            .get(rank)
+            .map_or(self.aggregated_cost(), Ranked::cost)
+    }
+}
 pub trait Simulation {
     type LeftNodeType: LeftNode + Send + Sync;
     type RightNodeType: RightNode + Send + Sync;
-
-    type CostFieldType: CostField<LeftNodeType = Self::LeftNodeType, RightNodeType = Self::RightNodeType>
-        + Send
-        + Sync;
+    /// Returns the previous and the current column, both indexed by row.
     fn prepare_step_slices(
         &mut self,
         iteration: usize,
     ) -> (&[Self::LeftNodeType], &mut [Self::RightNodeType]);
 
-    fn get_cost_field(&self) -> Arc<Self::CostFieldType>;
+    fn get_cost_field(&self) -> Arc<dyn CostField>;
+
+    fn max_slope(&self) -> Option<usize>;
+
+    fn constraints(&self) -> Arc<Constraints>;
+
+    /// Column of the grid that is computed in the given iteration.
+    fn column(&self, iteration: usize) -> usize {
+        iteration
+    }
 
+    /// Advances the simulation by one column, the first column only takes the start costs.
+    /// Nodes that cannot be reached, also because obstacles block every edge into them,
+    /// get an infinite cost and no parent.
     fn simulate_par(&mut self, iteration: usize) {
         let cost_field = self.get_cost_field();
+        let max_slope = self.max_slope();
+        let constraints = self.constraints();
+        let x = self.column(iteration);
+        let previous_x = iteration.checked_sub(1).map(|i| self.column(i));
+        let obstacles = previous_x.and_then(|previous_x| constraints.step_obstacles(previous_x, x));
+        let step = previous_x.map(|previous_x| cost_field.step(previous_x, x));
         let (previous, current) = self.prepare_step_slices(iteration);
-        current.par_iter_mut().for_each(|curr| {
-            if let Some((cost, prev_node)) = previous
-                .iter()
-                .filter_map(|prev| {
-                    Some((
-                        (cost_field.get_cost(prev, curr) + prev.aggregated_cost())?,
-                        prev,
-                    ))
-                })
-                .min_by(|(cost0, _), (cost1, _)| cost0.cmp(cost1))
-            {
+        current.par_iter_mut().enumerate().for_each(|(y, curr)| {
+            if !constraints.allows(x, y) {
+                curr.set_aggregated_cost(score::INFINITY);
+                return;
+            }
+            let step = match &step {
+                Some(step) if !previous.is_empty() => step,
+                _ => {
+                    curr.set_aggregated_cost(constraints.start.cost(y));
+                    return;
+                }
+            };
+            let window = predecessor_window(previous.len(), y, max_slope);
+            let first = window.start;
+            if let Some((cost, prev_node)) = lowest_minimum(
+                previous[window]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, prev)| {
+                        if is_blocked(&obstacles, first + i, y) {
+                            return None;
+                        }
+                        Some((
+                            (step.get_cost(first + i, y) + prev.aggregated_cost())?,
+                            prev,
+                        ))
+                    })
+                    .filter(|(cost, _)| cost.is_finite()),
+            ) {
                 curr.set_aggregated_cost(cost);
                 Self::set_parent_of(prev_node, curr);
+            } else {
+                curr.set_aggregated_cost(score::INFINITY);
             }
         });
     }
     fn set_parent_of(parent: &Self::LeftNodeType, child: &mut Self::RightNodeType);
 }
+
+pub trait KBestSimulation: Simulation
+where
+    Self::LeftNodeType: RankedNode,
+{
+    /// Like `simulate_par`, but keeps up to `k` trajectories per node instead of only the cheapest.
+    fn simulate_par_k_best(&mut self, iteration: usize, k: usize) {
+        let cost_field = self.get_cost_field();
+        let max_slope = self.max_slope();
+        let constraints = self.constraints();
+        let x = self.column(iteration);
+        let previous_x = iteration.checked_sub(1).map(|i| self.column(i));
+        let obstacles = previous_x.and_then(|previous_x| constraints.step_obstacles(previous_x, x));
+        let step = previous_x.map(|previous_x| cost_field.step(previous_x, x));
+        let (previous, current) = self.prepare_step_slices(iteration);
+        current.par_iter_mut().enumerate().for_each(|(y, curr)| {
+            if !constraints.allows(x, y) {
+                curr.set_aggregated_cost(score::INFINITY);
+                return;
+            }
+            let step = match &step {
+                Some(step) if !previous.is_empty() => step,
+                _ => {
+                    curr.set_aggregated_cost(constraints.start.cost(y));
+                    return;
+                }
+            };
+            let window = predecessor_window(previous.len(), y, max_slope);
+            let first = window.start;
+            let candidates = cheapest(
+                previous[window]
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !is_blocked(&obstacles, first + i, y))
+                    .flat_map(|(i, prev)| {
+                        let cost = step.get_cost(first + i, y);
+                        (0..prev.candidate_count()).filter_map(move |rank| {
+                            Some(((cost + prev.candidate_cost(rank))?, (prev, rank)))
+                        })
+                    })
+                    .filter(|(cost, _)| cost.is_finite())
+                    .collect(),
+                k,
+            );
+            curr.set_aggregated_cost(
+                candidates
+                    .first()
+                    .map_or(score::INFINITY, |&(cost, _)| cost),
+            );
+            for (cost, (prev_node, rank)) in candidates {
+                Self::add_candidate(prev_node, rank, cost, curr);
+            }
+        });
+    }
+    fn add_candidate(
+        parent: &Self::LeftNodeType,
+        rank: usize,
+        cost: Score,
+        child: &mut Self::RightNodeType,
+    );
+}
+
+/// The `k` cheapest feasible trajectory ends in the last column as `(cost, row, rank)`,
+/// with the cost of ending at the row already included.
+pub fn best_candidates<N: RankedNode>(
+    column: &[N],
+    k: usize,
+    constraints: &Constraints,
+) -> Vec<(Score, usize, usize)> {
+    let candidates = column
+        .iter()
+        .enumerate()
+        .flat_map(|(y, node)| {
+            (0..node.candidate_count()).map(move |rank| {
+                (
+                    constraints.final_cost(y, node.candidate_cost(rank)),
+                    (y, rank),
+                )
+            })
+        })
+        .filter(|(cost, _)| cost.is_finite())
+        .collect();
+    cheapest(candidates, k)
+        .into_iter()
+        .map(|(cost, (y, rank))| (cost, y, rank))
+        .collect()
+}
+
+/// The tie-break rule of every strategy: the first of the cheapest items wins. Items come by
+/// ascending row, so among predecessors or trajectory ends of equal cost the lowest row is taken.
+/// Costs are compared exactly, every strategy sums the edge costs of a trajectory in the same
+/// order, from the start column towards the target, so equal trajectories get equal costs.
+pub fn lowest_minimum<T>(items: impl IntoIterator<Item = (Score, T)>) -> Option<(Score, T)> {
+    items.into_iter().min_by_key(|&(cost, _)| cost)
+}
+
+/// Up to `k` of the items, cheapest first, where items of equal cost keep their order
+/// as in `lowest_minimum`.
+pub fn cheapest<T>(mut items: Vec<(Score, T)>, k: usize) -> Vec<(Score, T)> {
+    items.sort_by_key(|&(cost, _)| cost);
+    items.truncate(k);
+    items
+}
@@ -0,0 +1,401 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use structopt::StructOpt;
+
+use crate::image::ramp_color;
+use crate::simulation::SimulationOptions;
+use crate::trajectory::Trajectory;
+
+/// Decorations of the SVG rendering.
+#[derive(StructOpt, Debug, Clone)]
+pub struct SvgOptions {
+    /// Number of contour levels of the energy field in the SVG
+    #[structopt(long, default_value = "10")]
+    pub contours: usize,
+    /// Draw axes labelled in world coordinates around the SVG plot
+    #[structopt(long)]
+    pub axes: bool,
+    /// Draw a legend of the contour levels and trajectories next to the SVG plot
+    #[structopt(long)]
+    pub legend: bool,
+    /// Draw a scale bar below the SVG plot
+    #[structopt(long)]
+    pub scale_bar: bool,
+    /// World length of one cell, used by the axes and the scale bar
+    #[structopt(long, default_value = "1.0")]
+    pub cell_size: f64,
+    /// Unit of the world coordinates
+    #[structopt(long, default_value = "m")]
+    pub unit: String,
+}
+
+/// Same as the command line defaults.
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            contours: 10,
+            axes: false,
+            legend: false,
+            scale_bar: false,
+            cell_size: 1.0,
+            unit: "m".to_owned(),
+        }
+    }
+}
+
+/// Pixels on the longer side of the plot.
+const PLOT_SIZE: f64 = 800.0;
+/// The energy field is sampled at no more than this many cells per side for the contours.
+const MAX_SAMPLES: usize = 256;
+const MARGIN: f64 = 20.0;
+const AXES_MARGIN: f64 = 50.0;
+const SCALE_BAR_MARGIN: f64 = 40.0;
+const LEGEND_WIDTH: f64 = 200.0;
+
+/// Writes the trajectories as polylines over iso-contours of the energy field,
+/// the first trajectory in red and the others dashed in gray.
+/// The origin of the world coordinates is the top left corner of the grid.
+pub fn write_svg(
+    path: &str,
+    svg: &SvgOptions,
+    options: &SimulationOptions,
+    trajectories: &[Trajectory],
+) -> Result<(), Box<dyn Error>> {
+    if !(svg.cell_size > 0.0 && svg.cell_size.is_finite()) {
+        return Err(format!("invalid cell size {}", svg.cell_size).into());
+    }
+    let cell = PLOT_SIZE / options.width.max(options.height) as f64;
+    let (plot_width, plot_height) = (options.width as f64 * cell, options.height as f64 * cell);
+    let left = if svg.axes { AXES_MARGIN } else { MARGIN };
+    let top = MARGIN;
+    let mut bottom = if svg.axes { AXES_MARGIN } else { MARGIN };
+    if svg.scale_bar {
+        bottom += SCALE_BAR_MARGIN;
+    }
+    let right = if svg.legend { LEGEND_WIDTH } else { MARGIN };
+    let contours = Contours::new(options, svg.contours, cell);
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0:.0}" height="{1:.0}" viewBox="0 0 {0:.0} {1:.0}" font-family="sans-serif" font-size="12">"#,
+        left + plot_width + right,
+        top + plot_height + bottom
+    )?;
+    writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+    writeln!(out, r#"<g transform="translate({} {})">"#, left, top)?;
+    for (i, level) in contours.levels.iter().enumerate() {
+        writeln!(
+            out,
+            r#"<path d="{}" fill="none" stroke="{}" stroke-width="1"/>"#,
+            contours.trace(*level),
+            hex(contours.color(i))
+        )?;
+    }
+    for (i, trajectory) in trajectories.iter().enumerate().rev() {
+        let points: Vec<_> = trajectory
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(x, &y)| {
+                format!(
+                    "{:.1},{:.1}",
+                    (x as f64 + 0.5) * cell,
+                    (y as f64 + 0.5) * cell
+                )
+            })
+            .collect();
+        let style = if i == 0 {
+            r#"stroke="red" stroke-width="2""#
+        } else {
+            r#"stroke="gray" stroke-width="1" stroke-dasharray="4 2""#
+        };
+        writeln!(
+            out,
+            r#"<polyline points="{}" fill="none" {}/>"#,
+            points.join(" "),
+            style
+        )?;
+    }
+    writeln!(
+        out,
+        r#"<rect width="{:.1}" height="{:.1}" fill="none" stroke="black"/>"#,
+        plot_width, plot_height
+    )?;
+    let world_cell = cell / svg.cell_size;
+    if svg.axes {
+        write_axes(&mut out, svg, options, world_cell)?;
+    }
+    if svg.scale_bar {
+        let offset = if svg.axes { AXES_MARGIN } else { 0.0 };
+        let length = nice_step(options.width as f64 * svg.cell_size / 4.0);
+        let bar = length * world_cell;
+        let y = plot_height + offset + SCALE_BAR_MARGIN / 2.0;
+        writeln!(
+            out,
+            r#"<path d="M0 {0:.1} v6 h{1:.1} v-6" fill="none" stroke="black" stroke-width="1.5"/>"#,
+            y - 3.0,
+            bar
+        )?;
+        writeln!(
+            out,
+            r#"<text x="{:.1}" y="{:.1}">{} {}</text>"#,
+            bar + 8.0,
+            y + 4.0,
+            format_tick(length, length),
+            escape(&svg.unit)
+        )?;
+    }
+    if svg.legend {
+        write_legend(&mut out, &contours, trajectories, plot_width + MARGIN)?;
+    }
+    writeln!(out, "</g>")?;
+    writeln!(out, "</svg>")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_axes(
+    out: &mut impl Write,
+    svg: &SvgOptions,
+    options: &SimulationOptions,
+    world_cell: f64,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = (
+        options.width as f64 * svg.cell_size,
+        options.height as f64 * svg.cell_size,
+    );
+    let plot_height = height * world_cell;
+    let step = nice_step(width.max(height) / 6.0);
+    for (extent, horizontal) in [(width, true), (height, false)].iter() {
+        let mut tick = 0.0;
+        while tick <= extent + step * 1e-9 {
+            let position = tick * world_cell;
+            let label = format_tick(tick, step);
+            if *horizontal {
+                writeln!(
+                    out,
+                    r#"<path d="M{0:.1} {1:.1} v5" stroke="black"/><text x="{0:.1}" y="{2:.1}" text-anchor="middle">{3}</text>"#,
+                    position,
+                    plot_height,
+                    plot_height + 18.0,
+                    label
+                )?;
+            } else {
+                writeln!(
+                    out,
+                    r#"<path d="M0 {0:.1} h-5" stroke="black"/><text x="-8" y="{1:.1}" text-anchor="end">{2}</text>"#,
+                    position,
+                    position + 4.0,
+                    label
+                )?;
+            }
+            tick += step;
+        }
+    }
+    writeln!(
+        out,
+        r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">x [{}]</text>"#,
+        width * world_cell / 2.0,
+        plot_height + 36.0,
+        escape(&svg.unit)
+    )?;
+    writeln!(
+        out,
+        r#"<text transform="translate(-38 {:.1}) rotate(-90)" text-anchor="middle">y [{}]</text>"#,
+        plot_height / 2.0,
+        escape(&svg.unit)
+    )?;
+    Ok(())
+}
+
+fn write_legend(
+    out: &mut impl Write,
+    contours: &Contours,
+    trajectories: &[Trajectory],
+    x: f64,
+) -> Result<(), Box<dyn Error>> {
+    let mut y = 10.0;
+    writeln!(out, r#"<text x="{:.1}" y="{:.1}">energy</text>"#, x, y)?;
+    for (i, level) in contours.levels.iter().enumerate().rev() {
+        y += 16.0;
+        writeln!(
+            out,
+            r#"<path d="M{:.1} {:.1} h20" stroke="{}" stroke-width="2"/><text x="{:.1}" y="{:.1}">{:.3}</text>"#,
+            x,
+            y - 4.0,
+            hex(contours.color(i)),
+            x + 26.0,
+            y,
+            level
+        )?;
+    }
+    for (i, trajectory) in trajectories.iter().enumerate() {
+        y += if i == 0 { 28.0 } else { 16.0 };
+        let style = if i == 0 {
+            r#"stroke="red" stroke-width="2""#
+        } else {
+            r#"stroke="gray" stroke-width="1" stroke-dasharray="4 2""#
+        };
+        writeln!(
+            out,
+            r#"<path d="M{:.1} {:.1} h20" {}/><text x="{:.1}" y="{:.1}">cost {:.6}</text>"#,
+            x,
+            y - 4.0,
+            style,
+            x + 26.0,
+            y,
+            trajectory.cost.0
+        )?;
+    }
+    Ok(())
+}
+
+/// Replaces the characters with a meaning in XML text and attributes by their entities.
+fn escape(text: &str) -> String {
+    text.chars().fold(String::new(), |mut escaped, c| {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+        escaped
+    })
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// 1, 2 or 5 times a power of ten, whichever is closest to `raw`.
+fn nice_step(raw: f64) -> f64 {
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let normalized = raw / magnitude;
+    let factor = if normalized < 1.5 {
+        1.0
+    } else if normalized < 3.5 {
+        2.0
+    } else if normalized < 7.5 {
+        5.0
+    } else {
+        10.0
+    };
+    factor * magnitude
+}
+
+/// Prints `value` with as many decimals as `step` needs.
+fn format_tick(value: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    format!("{:.*}", decimals, value)
+}
+
+/// Energy field sampled on a coarse grid of cell centers, for marching squares.
+struct Contours {
+    columns: usize,
+    rows: usize,
+    /// Row-major energies of the samples.
+    energies: Vec<f64>,
+    /// Plot coordinates of the sampled columns and rows.
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    levels: Vec<f64>,
+}
+
+impl Contours {
+    fn new(options: &SimulationOptions, levels: usize, cell: f64) -> Self {
+        let sample = |count: usize, samples: usize| -> Vec<usize> {
+            if samples < 2 {
+                return vec![0; samples];
+            }
+            (0..samples)
+                .map(|i| i * (count - 1) / (samples - 1))
+                .collect()
+        };
+        let sampled_columns = sample(options.width, options.width.min(MAX_SAMPLES));
+        let sampled_rows = sample(options.height, options.height.min(MAX_SAMPLES));
+        let cost_field = &options.cost_field;
+        let energies: Vec<f64> = sampled_rows
+            .clone()
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                sampled_columns
+                    .iter()
+                    .map(move |&x| cost_field.cell_energy(x, y))
+            })
+            .collect();
+        let min = energies.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = energies.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let to_plot = |i: &usize| (*i as f64 + 0.5) * cell;
+        Contours {
+            columns: sampled_columns.len(),
+            rows: sampled_rows.len(),
+            energies,
+            xs: sampled_columns.iter().map(to_plot).collect(),
+            ys: sampled_rows.iter().map(to_plot).collect(),
+            levels: (1..=levels)
+                .map(|i| min + (max - min) * i as f64 / (levels + 1) as f64)
+                .collect(),
+        }
+    }
+
+    fn color(&self, level: usize) -> [u8; 3] {
+        ramp_color(level as f64 / self.levels.len().saturating_sub(1).max(1) as f64)
+    }
+
+    /// Path data of the iso-line at `level`, one move and line per square it crosses.
+    fn trace(&self, level: f64) -> String {
+        let mut d = String::new();
+        for j in 1..self.rows {
+            for i in 1..self.columns {
+                // Corners in clockwise order, starting at the top left.
+                let corners = [(i - 1, j - 1), (i, j - 1), (i, j), (i - 1, j)];
+                let value = |(x, y): (usize, usize)| self.energies[y * self.columns + x];
+                let above: Vec<bool> = corners.iter().map(|&c| value(c) >= level).collect();
+                // Crossing of each edge between a corner and the next one.
+                let crossings: Vec<Option<(f64, f64)>> = (0..4)
+                    .map(|edge| {
+                        let (p, q) = (corners[edge], corners[(edge + 1) % 4]);
+                        if above[edge] == above[(edge + 1) % 4] {
+                            return None;
+                        }
+                        let t = (level - value(p)) / (value(q) - value(p));
+                        Some((
+                            self.xs[p.0] + (self.xs[q.0] - self.xs[p.0]) * t,
+                            self.ys[p.1] + (self.ys[q.1] - self.ys[p.1]) * t,
+                        ))
+                    })
+                    .collect();
+                let points: Vec<_> = crossings.iter().flatten().collect();
+                let segments = match points.len() {
+                    2 => vec![(points[0], points[1])],
+                    4 => {
+                        // A saddle: the center decides whether the top left corner joins the
+                        // bottom right one, leaving the other two corners cut off, or the reverse.
+                        let center = corners.iter().map(|&c| value(c)).sum::<f64>() / 4.0;
+                        if (center >= level) == above[0] {
+                            vec![(points[0], points[1]), (points[2], points[3])]
+                        } else {
+                            vec![(points[3], points[0]), (points[1], points[2])]
+                        }
+                    }
+                    _ => Vec::new(),
+                };
+                for (from, to) in segments {
+                    d.push_str(&format!(
+                        "M{:.1} {:.1}L{:.1} {:.1}",
+                        from.0, from.1, to.0, to.1
+                    ));
+                }
+            }
+        }
+        d
+    }
+}
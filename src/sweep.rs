@@ -0,0 +1,274 @@
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    process::{Command, Stdio},
+    str::FromStr,
+    time::Instant,
+};
+
+use itertools::Itertools;
+use structopt::StructOpt;
+use trajectory_calculator::{
+    allocator::{self, Backend},
+    memory_profiler, SIMULATIONS,
+};
+
+/// Runs every strategy on every grid size with every allocator in a child process of its own,
+/// so that allocator state does not leak between runs, and collects their memory samples into
+/// one file
+#[derive(StructOpt, Debug)]
+pub struct SweepOptions {
+    /// Grid sizes as `WIDTHxHEIGHT`
+    #[structopt(long, use_delimiter = true)]
+    sizes: Vec<Size>,
+    /// Widths combined with every one of `--heights`
+    #[structopt(long, use_delimiter = true)]
+    widths: Vec<usize>,
+    /// Heights combined with every one of `--widths`
+    #[structopt(long, use_delimiter = true)]
+    heights: Vec<usize>,
+    /// Predefined sizes: square, long, const-nodes or all
+    #[structopt(long)]
+    preset: Option<Preset>,
+    /// Strategies to run, every registered one by default
+    #[structopt(short = "t", long = "strategies", use_delimiter = true)]
+    strategies: Vec<String>,
+    /// Allocators every configuration runs with, the one of the sweep by default
+    #[structopt(long, use_delimiter = true)]
+    allocators: Vec<Backend>,
+    /// Consolidated results, one row per memory sample with the run it belongs to in front
+    #[structopt(short = "o", long, default_value = "results/sweep.tsv")]
+    out_file: String,
+    /// Options passed to every run, after `--`
+    #[structopt(last = true)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Size {
+    width: usize,
+    height: usize,
+}
+
+impl FromStr for Size {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, height) = s.split_once('x').ok_or("expected `WIDTHxHEIGHT`")?;
+        Ok(Size {
+            width: width.parse()?,
+            height: height.parse()?,
+        })
+    }
+}
+
+/// The grids the memory comparison has been run on.
+#[derive(Debug, Clone, Copy)]
+enum Preset {
+    Square,
+    Long,
+    /// Grids with the same number of nodes and changing aspect ratio.
+    ConstNodes,
+    All,
+}
+
+impl FromStr for Preset {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "square" => Preset::Square,
+            "long" => Preset::Long,
+            "const-nodes" => Preset::ConstNodes,
+            "all" => Preset::All,
+            _ => return Err(format!("unknown preset `{}`", s).into()),
+        })
+    }
+}
+
+impl Preset {
+    fn sizes(self) -> Vec<Size> {
+        let size = |(width, height)| Size { width, height };
+        match self {
+            Preset::Square => [256, 512, 1024, 2048]
+                .iter()
+                .map(|&n| size((n, n)))
+                .collect(),
+            Preset::Long => [2048, 4096, 8192, 16384]
+                .iter()
+                .map(|&width| size((width, 256)))
+                .collect(),
+            Preset::ConstNodes => (6..=13).map(|i| size((1 << i, 1 << (19 - i)))).collect(),
+            Preset::All => [Preset::ConstNodes, Preset::Long, Preset::Square]
+                .iter()
+                .flat_map(|preset| preset.sizes())
+                .collect(),
+        }
+    }
+}
+
+impl SweepOptions {
+    fn sizes(&self) -> Vec<Size> {
+        let mut sizes = self.preset.map(Preset::sizes).unwrap_or_default();
+        sizes.extend(&self.sizes);
+        for &width in &self.widths {
+            for &height in &self.heights {
+                sizes.push(Size { width, height });
+            }
+        }
+        sizes
+    }
+
+    fn strategies(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.strategies.is_empty() {
+            let mut names: Vec<_> = SIMULATIONS.keys().map(|name| name.to_string()).collect();
+            names.sort();
+            return Ok(names);
+        }
+        for name in &self.strategies {
+            if !SIMULATIONS.contains_key(name.as_str()) {
+                return Err(format!("unknown simulation type `{}`", name).into());
+            }
+        }
+        Ok(self.strategies.clone())
+    }
+}
+
+/// The `# key: value` lines a run writes above its samples, repeated as columns of each of its rows.
+struct Header<'a>(Vec<(&'a str, &'a str)>);
+
+impl<'a> Header<'a> {
+    fn parse(samples: &'a str) -> Self {
+        Header(
+            samples
+                .lines()
+                .filter_map(|line| line.strip_prefix('#')?.split_once(':'))
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .collect(),
+        )
+    }
+
+    /// Value of `key`, `None` if it is missing or empty.
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.0
+            .iter()
+            .find(|&&(k, value)| k == key && !value.is_empty())
+            .map(|&(_, value)| value)
+    }
+
+    /// The `size_class.LIMIT: COUNT` lines as `LIMIT:COUNT,...`.
+    fn size_classes(&self) -> String {
+        self.0
+            .iter()
+            .filter_map(|&(key, count)| {
+                Some(format!("{}:{}", key.strip_prefix("size_class.")?, count))
+            })
+            .join(",")
+    }
+}
+
+pub fn sweep(options: SweepOptions) -> Result<(), Box<dyn Error>> {
+    let sizes = options.sizes();
+    if sizes.is_empty() {
+        return Err("no grid sizes given, use --sizes, --widths with --heights or --preset".into());
+    }
+    let strategies = options.strategies()?;
+    let allocators = if options.allocators.is_empty() {
+        vec![allocator::active().unwrap_or(Backend::Jemalloc)]
+    } else {
+        options.allocators.clone()
+    };
+    if let Some(directory) = Path::new(&options.out_file).parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let mut out = BufWriter::new(File::create(&options.out_file)?);
+    writeln!(out, "# extra arguments: {}", options.args.join(" "))?;
+    writeln!(
+        out,
+        "run\tstrategy\tallocator\twidth\theight\tstatus\tseconds\t\
+         seed\tthreads\tmemory_source\tgit_revision\tsize_classes\t{}",
+        memory_profiler::COLUMNS
+    )?;
+    let executable = env::current_exe()?;
+    let samples_file = env::temp_dir().join(format!("trajectory-sweep-{}.tsv", std::process::id()));
+    let runs = sizes.len() * strategies.len() * allocators.len();
+    let mut failed = 0;
+    for (run, (size, strategy, allocator)) in sizes
+        .iter()
+        .flat_map(|size| strategies.iter().map(move |strategy| (size, strategy)))
+        .flat_map(|(size, strategy)| {
+            allocators
+                .iter()
+                .map(move |allocator| (size, strategy, allocator))
+        })
+        .enumerate()
+    {
+        print!(
+            "[{}/{}] {} {} {}x{} ",
+            run + 1,
+            runs,
+            strategy,
+            allocator,
+            size.width,
+            size.height
+        );
+        std::io::stdout().flush()?;
+        let started = Instant::now();
+        let status = Command::new(&executable)
+            .args(["-t", strategy])
+            .args(["-x", &size.width.to_string()])
+            .args(["-y", &size.height.to_string()])
+            .arg("-o")
+            .arg(&samples_file)
+            .args(&options.args)
+            .env(allocator::ALLOCATOR_VAR, allocator.to_string())
+            .stdout(Stdio::null())
+            .status()?;
+        let seconds = started.elapsed().as_secs_f64();
+        println!("{:.2}s {}", seconds, status);
+        let samples = if status.success() {
+            fs::read_to_string(&samples_file)?
+        } else {
+            failed += 1;
+            String::new()
+        };
+        let header = Header::parse(&samples);
+        let requested = allocator.to_string();
+        let allocator = header.get("allocator").unwrap_or(&requested);
+        let metadata = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{}\t{}\t{}\t{}\t{}",
+            run,
+            strategy,
+            allocator,
+            size.width,
+            size.height,
+            status.code().map_or(-1, |code| code),
+            seconds,
+            header.get("seed").unwrap_or(""),
+            header.get("threads").unwrap_or(""),
+            header.get("memory_source").unwrap_or(""),
+            header.get("git_revision").unwrap_or(""),
+            header.size_classes()
+        );
+        let lines = samples
+            .lines()
+            .filter(|line| !line.starts_with('#') && *line != memory_profiler::COLUMNS);
+        let mut empty = true;
+        for line in lines {
+            writeln!(out, "{}\t{}", metadata, line)?;
+            empty = false;
+        }
+        if empty {
+            writeln!(out, "{}", metadata)?;
+        }
+        out.flush()?;
+    }
+    let _ = fs::remove_file(&samples_file);
+    if failed > 0 {
+        return Err(format!("{} of {} runs failed", failed, runs).into());
+    }
+    Ok(())
+}
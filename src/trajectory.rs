@@ -0,0 +1,224 @@
+use std::{error::Error, sync::Arc};
+
+use crate::constraints::{Boundary, Constraints, Gate};
+use crate::cost_field::{cache_if, CostField, HeightmapCostField, NoiseCostField, NoiseOptions};
+use crate::image::ColumnCosts;
+use crate::obstacles::ObstacleMask;
+use crate::raster::Raster;
+use crate::score::Score;
+use crate::simulate;
+use crate::simulation::SimulationOptions;
+
+/// A path through the grid, one row per column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trajectory {
+    pub rows: Vec<usize>,
+    /// Cost of the edge entering each column after the first one.
+    pub step_costs: Vec<Score>,
+    /// Sum of the step costs together with the start and target costs.
+    pub cost: Score,
+}
+
+impl Trajectory {
+    pub fn new(cost: Score, rows: Vec<usize>, cost_field: &dyn CostField) -> Self {
+        let step_costs = rows
+            .windows(2)
+            .enumerate()
+            .map(|(x, edge)| cost_field.get_cost(x, edge[0], x + 1, edge[1]))
+            .collect();
+        Trajectory {
+            rows,
+            step_costs,
+            cost,
+        }
+    }
+}
+
+enum CostFieldSource {
+    Noise(NoiseOptions),
+    Heightmap(Raster),
+    Custom(Arc<dyn CostField>),
+}
+
+/// Configures a simulation and runs one of the strategies registered in `SIMULATIONS`.
+///
+/// ```no_run
+/// use trajectory_calculator::TrajectoryBuilder;
+///
+/// let trajectory = TrajectoryBuilder::new(64, 32)
+///     .strategy("hirschberg")
+///     .max_slope(2)
+///     .solve()?;
+/// println!("{:?} costs {}", trajectory.rows, trajectory.cost.0);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct TrajectoryBuilder {
+    strategy: String,
+    width: usize,
+    height: usize,
+    max_slope: Option<usize>,
+    checkpoint_interval: Option<usize>,
+    paths: usize,
+    constraints: Constraints,
+    cost_field: CostFieldSource,
+    cache_costs: bool,
+    debug: bool,
+    progress: bool,
+    record_costs: bool,
+}
+
+impl TrajectoryBuilder {
+    /// Starts with the naive strategy on the default Perlin noise field.
+    pub fn new(width: usize, height: usize) -> Self {
+        TrajectoryBuilder {
+            strategy: "naive".to_owned(),
+            width,
+            height,
+            max_slope: None,
+            checkpoint_interval: None,
+            paths: 1,
+            constraints: Constraints::default(),
+            cost_field: CostFieldSource::Noise(NoiseOptions::default()),
+            cache_costs: false,
+            debug: false,
+            progress: false,
+            record_costs: false,
+        }
+    }
+
+    /// One of `naive`, `linear`, `rc`, `rc+`, `hirschberg` and `checkpoint`.
+    pub fn strategy(mut self, strategy: &str) -> Self {
+        self.strategy = strategy.to_owned();
+        self
+    }
+
+    pub fn max_slope(mut self, max_slope: impl Into<Option<usize>>) -> Self {
+        self.max_slope = max_slope.into();
+        self
+    }
+
+    pub fn checkpoint_interval(mut self, interval: impl Into<Option<usize>>) -> Self {
+        self.checkpoint_interval = interval.into();
+        self
+    }
+
+    /// Number of cheapest distinct trajectories `solve_all` looks for.
+    pub fn paths(mut self, paths: usize) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    pub fn start(mut self, start: Boundary) -> Self {
+        self.constraints.start = start;
+        self
+    }
+
+    pub fn target(mut self, target: Boundary) -> Self {
+        self.constraints.target = target;
+        self
+    }
+
+    pub fn gate(mut self, gate: Gate) -> Self {
+        self.constraints.gates.push(gate);
+        self
+    }
+
+    /// The mask is stretched over the grid.
+    pub fn obstacles(mut self, obstacles: ObstacleMask) -> Self {
+        self.constraints.obstacles = Some(obstacles);
+        self
+    }
+
+    pub fn noise(mut self, noise: NoiseOptions) -> Self {
+        self.cost_field = CostFieldSource::Noise(noise);
+        self
+    }
+
+    pub fn heightmap(mut self, heightmap: Raster) -> Self {
+        self.cost_field = CostFieldSource::Heightmap(heightmap);
+        self
+    }
+
+    pub fn cost_field(mut self, cost_field: Arc<dyn CostField>) -> Self {
+        self.cost_field = CostFieldSource::Custom(cost_field);
+        self
+    }
+
+    /// Caches the noise or heightmap energies of each step, see `CachedCostField`.
+    pub fn cache_costs(mut self, cache_costs: bool) -> Self {
+        self.cache_costs = cache_costs;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Keeps the aggregated costs of every column for the cost image.
+    pub fn record_costs(mut self, record_costs: bool) -> Self {
+        self.record_costs = record_costs;
+        self
+    }
+
+    /// Validates the configuration and resolves it into the options every strategy takes.
+    pub fn options(self) -> Result<SimulationOptions, Box<dyn Error>> {
+        if self.width == 0 || self.height == 0 {
+            return Err("the simulation needs at least one column and one row".into());
+        }
+        if self.paths == 0 {
+            return Err("the simulation has to find at least one trajectory".into());
+        }
+        let mut constraints = self.constraints;
+        if let Some(obstacles) = &mut constraints.obstacles {
+            obstacles.fit(self.width, self.height);
+        }
+        constraints.validate(self.width, self.height)?;
+        let (width, height) = (self.width, self.height);
+        let cost_field = match self.cost_field {
+            CostFieldSource::Noise(noise) => cache_if(
+                self.cache_costs,
+                height,
+                NoiseCostField::new(width, height, &noise),
+            ),
+            CostFieldSource::Heightmap(heightmap) => cache_if(
+                self.cache_costs,
+                height,
+                HeightmapCostField::new(width, height, heightmap),
+            ),
+            CostFieldSource::Custom(cost_field) => cost_field,
+        };
+        Ok(SimulationOptions {
+            width,
+            height,
+            max_slope: self.max_slope,
+            checkpoint_interval: self.checkpoint_interval,
+            paths: self.paths,
+            constraints: Arc::new(constraints),
+            cost_field,
+            debug: self.debug,
+            progress: self.progress,
+            column_costs: if self.record_costs {
+                Some(Arc::new(ColumnCosts::new(width, height)))
+            } else {
+                None
+            },
+        })
+    }
+
+    /// The `paths` cheapest trajectories, cheapest first.
+    pub fn solve_all(self) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+        let strategy = self.strategy.clone();
+        simulate(&strategy, &self.options()?)
+    }
+
+    /// The cheapest trajectory.
+    pub fn solve(self) -> Result<Trajectory, Box<dyn Error>> {
+        Ok(self.paths(1).solve_all()?.remove(0))
+    }
+}
@@ -1 +1,41 @@
+use std::fmt::{self, Formatter};
 
+use crate::score::Score;
+
+/// Draws which nodes of the columns before the frontier are still referenced from it,
+/// followed by the frontier costs, with the trajectory highlighted.
+pub fn fmt_ancestor_tree(
+    f: &mut Formatter<'_>,
+    alive: &[Vec<bool>],
+    frontier: &[Score],
+    path: &[usize],
+) -> fmt::Result {
+    let referenced = alive.iter().flatten().filter(|&&alive| alive).count();
+    writeln!(
+        f,
+        "{} of {} nodes before the frontier are still referenced (o), the rest was freed (.)",
+        referenced,
+        alive.len() * frontier.len()
+    )?;
+    for (y, cost) in frontier.iter().enumerate() {
+        for (x, column) in alive.iter().enumerate() {
+            if path.get(x) == Some(&y) {
+                write!(f, "\x1b[38;2;0;255;0m * \x1b[0m")?;
+            } else if column[y] {
+                write!(f, " o ")?;
+            } else {
+                write!(f, " . ")?;
+            }
+        }
+        if path.last() == Some(&y) {
+            write!(f, "\x1b[38;2;0;255;0m")?;
+        }
+        if cost.is_finite() {
+            write!(f, "{:+.2}", cost.0)?;
+        } else {
+            write!(f, " x ")?;
+        }
+        writeln!(f, "\x1b[0m")?;
+    }
+    Ok(())
+}
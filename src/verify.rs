@@ -0,0 +1,184 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::constraints::Constraints;
+use crate::simulation::SimulationOptions;
+use crate::trajectory::Trajectory;
+use crate::SIMULATIONS;
+
+/// Relative difference below which `recomputed_cost` matches the cost reported by a strategy.
+/// It adds the boundary costs after the edge costs, so equal costs may differ in the last bits.
+/// The strategies agree exactly with each other.
+pub const TOLERANCE: f64 = 1e-9;
+
+/// The cheapest trajectory found by every registered strategy on the same scenario,
+/// compared against the `naive` one.
+///
+/// All strategies break ties between trajectories of equal cost by `simulation::lowest_minimum`,
+/// so any other path is a mismatch, even if it costs the same.
+pub struct Verification {
+    /// The reference comes first, the others follow in alphabetical order.
+    pub results: Vec<(&'static str, Result<Trajectory, String>)>,
+    constraints: Constraints,
+}
+
+/// Runs every strategy for the cheapest trajectory only, without progress output.
+pub fn verify(options: &SimulationOptions) -> Verification {
+    let mut names: Vec<_> = SIMULATIONS.keys().copied().collect();
+    names.sort_by_key(|&name| (name != "naive", name));
+    let options = SimulationOptions {
+        paths: 1,
+        progress: false,
+        ..options.clone()
+    };
+    let results = names
+        .into_iter()
+        .map(|name| {
+            let result = SIMULATIONS[name](&options)
+                .map(|mut trajectories| trajectories.remove(0))
+                .map_err(|error| error.to_string());
+            (name, result)
+        })
+        .collect();
+    Verification {
+        results,
+        constraints: (*options.constraints).clone(),
+    }
+}
+
+enum Outcome {
+    Same,
+    Mismatch(String),
+}
+
+impl Verification {
+    pub fn agrees(&self) -> bool {
+        self.results
+            .iter()
+            .all(|(_, result)| !matches!(self.compare(result), Outcome::Mismatch(_)))
+    }
+
+    /// The reference trajectory if all strategies agree.
+    pub fn into_result(self) -> Result<Vec<Trajectory>, Box<dyn Error>> {
+        if !self.agrees() {
+            return Err("the strategies disagree".into());
+        }
+        let (_, reference) = self.results.into_iter().next().ok_or("no strategies")?;
+        Ok(vec![reference?])
+    }
+
+    fn compare(&self, result: &Result<Trajectory, String>) -> Outcome {
+        let reference = &self.results[0].1;
+        let (reference, trajectory) = match (reference, result) {
+            (Err(expected), Err(error)) if expected == error => return Outcome::Same,
+            (Err(expected), Err(error)) => {
+                return Outcome::Mismatch(format!(
+                    "failed with `{}` instead of `{}`",
+                    error, expected
+                ))
+            }
+            (Err(expected), Ok(_)) => {
+                return Outcome::Mismatch(format!(
+                    "succeeded, but the reference failed with `{}`",
+                    expected
+                ))
+            }
+            (Ok(_), Err(error)) => return Outcome::Mismatch(format!("failed with `{}`", error)),
+            (Ok(reference), Ok(trajectory)) => (reference, trajectory),
+        };
+        let recomputed = recomputed_cost(&self.constraints, trajectory);
+        if !same_cost(trajectory.cost.0, recomputed) {
+            return Outcome::Mismatch(format!(
+                "reports a cost of {:.9}, but its path costs {:.9}",
+                trajectory.cost.0, recomputed
+            ));
+        }
+        if reference.cost != trajectory.cost {
+            return Outcome::Mismatch(format!(
+                "costs {:.9} instead of {:.9}",
+                trajectory.cost.0, reference.cost.0
+            ));
+        }
+        if reference.rows == trajectory.rows {
+            Outcome::Same
+        } else {
+            Outcome::Mismatch("takes a different path".to_owned())
+        }
+    }
+}
+
+/// Whether both costs are the same within `TOLERANCE`.
+pub fn same_cost(a: f64, b: f64) -> bool {
+    (a - b).abs() <= TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
+
+/// Cost of the path from the cost field, independent of how the strategy summed it up.
+pub fn recomputed_cost(constraints: &Constraints, trajectory: &Trajectory) -> f64 {
+    let rows = &trajectory.rows;
+    constraints.start.cost(rows[0]).0
+        + trajectory.step_costs.iter().map(|cost| cost.0).sum::<f64>()
+        + constraints.target.cost(rows[rows.len() - 1]).0
+}
+
+/// Columns where the rows differ, merged into runs.
+fn fmt_diff(
+    f: &mut Formatter<'_>,
+    reference: (&str, &[usize]),
+    other: (&str, &[usize]),
+) -> fmt::Result {
+    let (mut x, width) = (0, reference.1.len().min(other.1.len()));
+    while x < width {
+        if reference.1[x] == other.1[x] {
+            x += 1;
+            continue;
+        }
+        let first = x;
+        while x < width && reference.1[x] != other.1[x] {
+            x += 1;
+        }
+        writeln!(
+            f,
+            "    columns {}..={}: {} {:?}, {} {:?}",
+            first,
+            x - 1,
+            reference.0,
+            &reference.1[first..x],
+            other.0,
+            &other.1[first..x]
+        )?;
+    }
+    Ok(())
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (reference_name, reference) = &self.results[0];
+        for (i, (name, result)) in self.results.iter().enumerate() {
+            match result {
+                Ok(trajectory) => write!(f, "{:<12} {:.9}", name, trajectory.cost.0)?,
+                Err(error) => write!(f, "{:<12} {}", name, error)?,
+            }
+            if i == 0 {
+                writeln!(f, " (reference)")?;
+                continue;
+            }
+            let outcome = self.compare(result);
+            match &outcome {
+                Outcome::Same => writeln!(f, " ok")?,
+                Outcome::Mismatch(reason) => writeln!(f, " MISMATCH: {}", reason)?,
+            }
+            if let (Outcome::Mismatch(_), Ok(reference), Ok(trajectory)) =
+                (&outcome, reference, result)
+            {
+                fmt_diff(
+                    f,
+                    (reference_name, &reference.rows),
+                    (name, &trajectory.rows),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
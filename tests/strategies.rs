@@ -0,0 +1,253 @@
+use proptest::prelude::*;
+use trajectory_calculator::{
+    brute_force::brute_force,
+    constraints::{Boundary, Gate},
+    cost_field::{NoiseOptions, NoiseType},
+    obstacles::ObstacleMask,
+    raster::Raster,
+    simulation::SimulationOptions,
+    verify::{recomputed_cost, same_cost},
+    Trajectory, TrajectoryBuilder, SIMULATIONS,
+};
+
+/// Strategies that can find more than one trajectory.
+const K_BEST: [&str; 4] = ["naive", "linear", "rc", "rc+"];
+
+#[derive(Debug, Clone)]
+struct Scenario {
+    width: usize,
+    height: usize,
+    max_slope: Option<usize>,
+    noise_type: NoiseType,
+    seed: u32,
+    /// 3x3 heightmap used instead of the noise.
+    heightmap: Option<Vec<u16>>,
+    start: Option<usize>,
+    target: Option<usize>,
+    waypoint: Option<(usize, usize)>,
+    /// 3x3 obstacle mask, `true` is blocked.
+    obstacles: Option<Vec<bool>>,
+    cache_costs: bool,
+}
+
+impl Scenario {
+    fn options(&self, paths: usize) -> SimulationOptions {
+        let mut builder = TrajectoryBuilder::new(self.width, self.height)
+            .max_slope(self.max_slope)
+            .paths(paths)
+            .noise(NoiseOptions {
+                noise_type: self.noise_type,
+                seed: self.seed,
+                ..NoiseOptions::default()
+            })
+            .cache_costs(self.cache_costs);
+        if let Some(samples) = &self.heightmap {
+            builder = builder.heightmap(Raster {
+                width: 3,
+                height: 3,
+                max_value: 255,
+                samples: samples.clone(),
+            });
+        }
+        if let Some(row) = self.start {
+            builder = builder.start(Boundary::Row(row % self.height));
+        }
+        if let Some(row) = self.target {
+            builder = builder.target(Boundary::Row(row % self.height));
+        }
+        if let Some((column, row)) = self.waypoint {
+            let row = row % self.height;
+            builder = builder.gate(Gate {
+                column: column % self.width,
+                rows: row..=row,
+            });
+        }
+        if let Some(blocked) = &self.obstacles {
+            let mask: String = blocked
+                .chunks(3)
+                .map(|row| {
+                    let mut row: String = row.iter().map(|&b| if b { '#' } else { '.' }).collect();
+                    row.push('\n');
+                    row
+                })
+                .collect();
+            builder = builder.obstacles(mask.parse::<ObstacleMask>().unwrap());
+        }
+        builder.options().unwrap()
+    }
+}
+
+fn noise_type() -> impl Strategy<Value = NoiseType> {
+    prop_oneof![
+        Just(NoiseType::Perlin),
+        Just(NoiseType::Fbm),
+        Just(NoiseType::OpenSimplex),
+        Just(NoiseType::Worley),
+        Just(NoiseType::RidgedMulti),
+        Just(NoiseType::Billow),
+    ]
+}
+
+prop_compose! {
+    fn scenario()(
+        width in 1..=6usize,
+        height in 1..=5usize,
+        max_slope in proptest::option::of(0..3usize),
+        noise_type in noise_type(),
+        seed in any::<u32>(),
+        heightmap in proptest::option::weighted(0.3, proptest::collection::vec(0..=255u16, 9)),
+        start in proptest::option::weighted(0.3, 0..5usize),
+        target in proptest::option::weighted(0.3, 0..5usize),
+        waypoint in proptest::option::weighted(0.2, (0..6usize, 0..5usize)),
+        obstacles in proptest::option::weighted(0.2, proptest::collection::vec(prop::bool::weighted(0.2), 9)),
+        cache_costs in any::<bool>(),
+    ) -> Scenario {
+        Scenario {
+            width,
+            height,
+            max_slope,
+            noise_type,
+            seed,
+            heightmap,
+            start,
+            target,
+            waypoint,
+            obstacles,
+            cache_costs,
+        }
+    }
+}
+
+fn check_path(options: &SimulationOptions, trajectory: &Trajectory) -> Result<(), TestCaseError> {
+    let rows = &trajectory.rows;
+    prop_assert_eq!(rows.len(), options.width);
+    for (x, &y) in rows.iter().enumerate() {
+        prop_assert!(y < options.height);
+        prop_assert!(
+            options.constraints.allows(x, y),
+            "row {} of column {}",
+            y,
+            x
+        );
+    }
+    if let Some(max_slope) = options.max_slope {
+        for edge in rows.windows(2) {
+            prop_assert!(edge[0].max(edge[1]) - edge[0].min(edge[1]) <= max_slope);
+        }
+    }
+    let recomputed = recomputed_cost(&options.constraints, trajectory);
+    prop_assert!(
+        same_cost(trajectory.cost.0, recomputed),
+        "reported {} but the path costs {}",
+        trajectory.cost.0,
+        recomputed
+    );
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn every_strategy_finds_the_cheapest_trajectory(scenario in scenario()) {
+        let options = scenario.options(1);
+        let expected = brute_force(&options);
+        for (name, simulation) in SIMULATIONS.iter() {
+            match (&expected, simulation(&options)) {
+                (Ok(expected), Ok(found)) => {
+                    prop_assert_eq!(found.len(), 1, "{}", name);
+                    check_path(&options, &found[0])?;
+                    prop_assert!(
+                        same_cost(found[0].cost.0, expected[0].cost.0),
+                        "{} found {:?} costing {}, the cheapest is {:?} costing {}",
+                        name,
+                        found[0].rows,
+                        found[0].cost.0,
+                        expected[0].rows,
+                        expected[0].cost.0
+                    );
+                }
+                (Err(_), Err(_)) => {}
+                (expected, found) => prop_assert!(
+                    false,
+                    "{} returned {:?}, brute force {:?}",
+                    name,
+                    found.map(|t| t[0].rows.clone()).map_err(|e| e.to_string()),
+                    expected.as_ref().map(|t| t[0].rows.clone()).map_err(|e| e.to_string())
+                ),
+            }
+        }
+    }
+
+    /// A flat heightmap makes many trajectories cost the same, all strategies have to pick the same one.
+    #[test]
+    fn every_strategy_breaks_ties_alike(mut scenario in scenario(), level in 0..=255u16) {
+        scenario.heightmap = Some(vec![level; 9]);
+        let options = scenario.options(1);
+        let expected = SIMULATIONS["naive"](&options).map(|mut t| t.remove(0).rows);
+        for (name, simulation) in SIMULATIONS.iter() {
+            let found = simulation(&options).map(|mut t| t.remove(0).rows);
+            match (&expected, &found) {
+                (Ok(expected), Ok(found)) => prop_assert_eq!(found, expected, "{}", name),
+                (Err(_), Err(_)) => {}
+                _ => prop_assert!(false, "{} returned {:?}, naive {:?}", name, found.map_err(|e| e.to_string()), expected.as_ref().map_err(|e| e.to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn k_best_strategies_find_the_cheapest_distinct_trajectories(
+        scenario in scenario(),
+        paths in 2..5usize,
+    ) {
+        let options = scenario.options(paths);
+        let expected = brute_force(&options);
+        for name in K_BEST.iter() {
+            match (&expected, SIMULATIONS[name](&options)) {
+                (Ok(expected), Ok(found)) => {
+                    prop_assert_eq!(found.len(), expected.len(), "{}", name);
+                    for (found, expected) in found.iter().zip(expected) {
+                        check_path(&options, found)?;
+                        prop_assert!(
+                            same_cost(found.cost.0, expected.cost.0),
+                            "{} found a trajectory costing {}, expected {}",
+                            name,
+                            found.cost.0,
+                            expected.cost.0
+                        );
+                    }
+                    for (i, trajectory) in found.iter().enumerate() {
+                        prop_assert!(
+                            found[..i].iter().all(|other| other.rows != trajectory.rows),
+                            "{} returned {:?} twice",
+                            name,
+                            trajectory.rows
+                        );
+                    }
+                }
+                (Err(_), Err(_)) => {}
+                (expected, found) => prop_assert!(
+                    false,
+                    "{} returned {:?}, brute force {:?}",
+                    name,
+                    found.map(|t| t.len()).map_err(|e| e.to_string()),
+                    expected.as_ref().map(|t| t.len()).map_err(|e| e.to_string())
+                ),
+            }
+        }
+    }
+}
+
+/// A grid of one column is solved without any step, the gate alone decides the row.
+#[test]
+fn every_strategy_respects_a_gate_in_a_single_column() {
+    let options = TrajectoryBuilder::new(1, 5)
+        .gate(Gate {
+            column: 0,
+            rows: 3..=3,
+        })
+        .options()
+        .unwrap();
+    for (name, simulation) in SIMULATIONS.iter() {
+        let found = simulation(&options).unwrap();
+        assert_eq!(found[0].rows, [3], "{}", name);
+    }
+}